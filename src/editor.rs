@@ -1,18 +1,48 @@
 use core::cmp::min;
-use crossterm::event::{Event, KeyCode, KeyEvent, KeyEventKind, KeyModifiers, read};
+use crossterm::event::{
+    Event, KeyCode, KeyEvent, KeyEventKind, KeyModifiers, MouseButton, MouseEvent, MouseEventKind,
+};
 use std::{
     env,
+    fs::canonicalize,
     io::Error,
     panic::{set_hook, take_hook},
+    rc::Rc,
 };
-use terminal::{Position, Size, Terminal};
+use terminal::{CaretStyle, Position, Size, Terminal};
+use theme::Theme;
 
+mod annotatedstring;
+mod arguments;
+mod clipboard;
+mod command;
+mod config;
+mod filetype;
+mod messagebar;
+mod mode;
+mod pane;
+mod positions;
+mod recentfiles;
+mod spell;
+mod statusbar;
 mod terminal;
+mod theme;
 mod view;
 
-use view::View;
+use arguments::Arguments;
+use clipboard::Clipboard;
+use command::{Command, Move, Setting};
+use config::Config;
+use messagebar::MessageBar;
+use mode::{Mode, RepeatableEdit};
+use pane::Pane;
+use positions::Positions;
+use recentfiles::RecentFiles;
+use spell::Dictionary;
+use statusbar::StatusBar;
+use view::{CaretPlacement, CaseTransform, LineNumbers, Query, SearchDirection, View, Viewport};
 
-#[derive(Clone, Copy, Default)]
+#[derive(Clone, Copy, Default, PartialEq, Eq, Debug)]
 struct Location {
     x: usize,
     y: usize,
@@ -20,9 +50,29 @@ struct Location {
 
 #[derive(Default)]
 pub struct Editor {
+    config: Config,
     should_quit: bool,
-    location: Location,
-    view: View,
+    quit_times: u8,
+    mode: Option<Mode>,
+    pending_key: Option<char>,
+    /// A repeat count typed in Normal mode, for the next motion or command.
+    pending_count: Option<usize>,
+    /// The last edit made from Normal mode, for `.` to repeat.
+    last_edit: Option<RepeatableEdit>,
+    /// What has been typed since entering Insert mode from Normal mode.
+    insert_session: Option<RepeatableEdit>,
+    views: Vec<View>,
+    panes: Vec<Pane>,
+    active_pane: usize,
+    terminal_size: Size,
+    status_bar: StatusBar,
+    message_bar: MessageBar,
+    clipboard: Clipboard,
+    positions: Positions,
+    recent_files: RecentFiles,
+    /// The word list for spell checking, read the first time it is needed.
+    dictionary: Option<Rc<Dictionary>>,
+    last_caret: Option<Position>,
 }
 
 impl Editor {
@@ -32,26 +82,322 @@ impl Editor {
             let _ = Terminal::terminate();
             current_hook(panic_info);
         }));
+        Self::start(&Arguments::parse(env::args().skip(1)), Config::load())
+    }
+
+    /// Sets up the terminal and opens what `arguments` ask for, reporting a
+    /// config that failed to load in the message bar.
+    fn start(arguments: &Arguments, config: Result<Config, Error>) -> Result<Self, Error> {
         Terminal::initialize()?;
-        let mut view = View::default();
-        let args: Vec<String> = env::args().collect();
-        if let Some(file_name) = args.get(1) {
-            view.load(file_name);
+        let (config, config_error) = match config {
+            Ok(config) => (config, None),
+            Err(err) => (Config::default(), Some(err)),
+        };
+        let _ = Terminal::set_caret_style(arguments.caret_style.unwrap_or(config.caret_style));
+        let theme = Theme::by_name(&config.theme);
+        Terminal::set_theme(theme.unwrap_or_default());
+        let positions = Positions::load();
+        let mut recent_files = RecentFiles::load();
+        let mut views = Vec::new();
+        let mut load_errors = Vec::new();
+        for file_name in &arguments.file_names {
+            let mut view = Self::configured_view(&config);
+            if view.load(file_name).is_err() {
+                load_errors.push(file_name.as_str());
+                continue;
+            }
+            if let Some(at) = positions.get(file_name) {
+                view.restore_caret(at);
+            }
+            recent_files.push(file_name);
+            if arguments.read_only {
+                view.set_read_only(true);
+            }
+            views.push(view);
+        }
+        if views.is_empty() {
+            views.push(Self::configured_view(&config));
+        }
+        if let Some(line_number) = arguments.line_number
+            && let Some(view) = views.first_mut()
+        {
+            view.go_to_line(line_number);
         }
-        Ok(Self {
+        let mut editor = Self {
+            quit_times: config.quit_times,
+            config,
             should_quit: false,
-            location: Location::default(),
-            view,
-        })
+            mode: None,
+            pending_key: None,
+            pending_count: None,
+            last_edit: None,
+            insert_session: None,
+            views,
+            panes: vec![Pane::default()],
+            active_pane: 0,
+            terminal_size: Size::default(),
+            status_bar: StatusBar::default(),
+            message_bar: MessageBar::default(),
+            clipboard: Clipboard::default(),
+            positions,
+            recent_files,
+            dictionary: None,
+            last_caret: None,
+        };
+        if arguments.modal {
+            editor.set_mode(Mode::Normal);
+        }
+        editor.resize(Terminal::size().unwrap_or_default());
+        let dictionary_error = if editor.config.spell_check {
+            match editor.load_dictionary() {
+                Ok(dictionary) => {
+                    for view in &mut editor.views {
+                        view.set_dictionary(Some(Rc::clone(&dictionary)));
+                    }
+                    None
+                }
+                Err(err) => Some(err),
+            }
+        } else {
+            None
+        };
+        if !load_errors.is_empty() {
+            editor.set_status_message(&format!(
+                "ERR: Could not open file: {}",
+                load_errors.join(", ")
+            ));
+        } else if let Some(err) = config_error {
+            editor.set_status_message(&format!("ERR: Bad config file, using defaults: {err}"));
+        } else if let Some(err) = dictionary_error {
+            editor.set_status_message(&err);
+        } else if theme.is_none() {
+            editor.set_status_message(&format!("ERR: Unknown theme: {}", editor.config.theme));
+        } else if !arguments.unknown.is_empty() {
+            editor.set_status_message(&format!(
+                "Ignored arguments: {}",
+                arguments.unknown.join(" ")
+            ));
+        } else {
+            editor.set_status_message("HELP: Ctrl-S = save | Ctrl-Q = quit");
+        }
+        Ok(editor)
+    }
+
+    fn configured_view(config: &Config) -> View {
+        let mut view = View::default();
+        view.set_options(config.options());
+        if config.show_line_numbers {
+            view.set_line_numbers(LineNumbers::Absolute);
+        }
+        view
+    }
+
+    /// The buffer shown in the focused pane. There is always at least one.
+    fn view(&self) -> &View {
+        &self.views[self.panes[self.active_pane].view]
+    }
+
+    fn view_mut(&mut self) -> &mut View {
+        &mut self.views[self.panes[self.active_pane].view]
+    }
+
+    /// Puts the pane's own viewport into its view, or back again.
+    fn swap_pane_viewport(&mut self, pane_idx: usize) {
+        let pane = &mut self.panes[pane_idx];
+        self.views[pane.view].swap_viewport(&mut pane.viewport);
+    }
+
+    /// Runs `f` on the view shown in the given pane, as that pane sees it.
+    fn with_pane<R>(&mut self, pane_idx: usize, f: impl FnOnce(&mut View) -> R) -> R {
+        if pane_idx == self.active_pane {
+            return f(self.view_mut());
+        }
+        self.swap_pane_viewport(pane_idx);
+        let result = f(&mut self.views[self.panes[pane_idx].view]);
+        self.swap_pane_viewport(pane_idx);
+        result
+    }
+
+    /// Shows the next or previous buffer in the focused pane, where it picks
+    /// up its own caret and scroll offset again.
+    fn switch_view(&mut self, forward: bool) {
+        let count = self.views.len();
+        let current = self.panes[self.active_pane].view;
+        let view_idx = if forward {
+            current.saturating_add(1).checked_rem(count)
+        } else {
+            current
+                .saturating_add(count)
+                .saturating_sub(1)
+                .checked_rem(count)
+        }
+        .unwrap_or_default();
+        self.show_view(view_idx);
+    }
+
+    fn show_view(&mut self, view_idx: usize) {
+        self.remember_position(self.panes[self.active_pane].view);
+        let _ = self.positions.save();
+        let pane = &mut self.panes[self.active_pane];
+        pane.view = view_idx;
+        pane.viewport = Viewport::default();
+        self.layout_panes();
+        self.view_mut().mark_all_dirty();
+    }
+
+    /// Shows `file_name` in the focused pane, opening it unless some buffer
+    /// already holds it.
+    fn open_file(&mut self, file_name: &str) {
+        let path = canonicalize(file_name).ok();
+        let open = self.views.iter().position(|view| {
+            view.file_name().is_some_and(|open| {
+                open == file_name || (path.is_some() && canonicalize(open).ok() == path)
+            })
+        });
+        let view_idx = if let Some(view_idx) = open {
+            view_idx
+        } else {
+            let mut view = Self::configured_view(&self.config);
+            if self.config.spell_check {
+                view.set_dictionary(self.dictionary.clone());
+            }
+            if view.load(file_name).is_err() {
+                self.set_status_message(&format!("ERR: Could not open file: {file_name}"));
+                return;
+            }
+            if let Some(at) = self.positions.get(file_name) {
+                view.restore_caret(at);
+            }
+            self.recent_files.push(file_name);
+            self.views.push(view);
+            self.views.len().saturating_sub(1)
+        };
+        self.show_view(view_idx);
+    }
+
+    fn remember_position(&mut self, view_idx: usize) {
+        let view = &self.views[view_idx];
+        if let Some(file_name) = view.file_name() {
+            self.positions.set(file_name, view.caret());
+        }
+    }
+
+    fn split_pane(&mut self) {
+        let pane = Pane {
+            view: self.panes[self.active_pane].view,
+            viewport: self.view().viewport().clone(),
+        };
+        self.panes.insert(self.active_pane.saturating_add(1), pane);
+        self.layout_panes();
+    }
+
+    fn focus_next_pane(&mut self) {
+        let next = self
+            .active_pane
+            .saturating_add(1)
+            .checked_rem(self.panes.len())
+            .unwrap_or_default();
+        self.focus_pane(next);
+    }
+
+    fn focus_pane(&mut self, pane_idx: usize) {
+        if pane_idx != self.active_pane {
+            self.swap_pane_viewport(self.active_pane);
+            self.active_pane = pane_idx;
+            self.swap_pane_viewport(self.active_pane);
+        }
+    }
+
+    /// Focuses the pane under `at` and moves its caret there. Clicks on the
+    /// dividers and bars are ignored.
+    fn click(&mut self, at: Position) {
+        if let Some(pane_idx) = self.pane_at(at.row) {
+            self.focus_pane(pane_idx);
+            self.view_mut().click(at);
+        }
+    }
+
+    fn pane_at(&self, row: usize) -> Option<usize> {
+        pane::layout(self.panes.len(), self.text_area_size())
+            .into_iter()
+            .position(|(origin_row, size)| {
+                (origin_row..origin_row.saturating_add(size.height)).contains(&row)
+            })
+    }
+
+    /// Closes the focused pane. Its view keeps the closed pane's caret.
+    fn close_pane(&mut self) {
+        if self.panes.len() == 1 {
+            self.set_status_message("Only one pane is open.");
+            return;
+        }
+        self.panes.remove(self.active_pane);
+        self.active_pane = min(self.active_pane, self.panes.len().saturating_sub(1));
+        self.swap_pane_viewport(self.active_pane);
+        self.layout_panes();
+    }
+
+    /// The rows above the status and message bars, shared by all panes.
+    const fn text_area_size(&self) -> Size {
+        Size {
+            height: self.terminal_size.height.saturating_sub(2),
+            width: self.terminal_size.width,
+        }
+    }
+
+    fn layout_panes(&mut self) {
+        for (pane_idx, (origin_row, size)) in pane::layout(self.panes.len(), self.text_area_size())
+            .into_iter()
+            .enumerate()
+        {
+            self.with_pane(pane_idx, |view| {
+                view.place(origin_row, size);
+                view.scroll_into_view();
+            });
+        }
+    }
+
+    fn cycle_theme(&mut self) {
+        let theme = Terminal::theme().next();
+        Terminal::set_theme(theme);
+        for view in &mut self.views {
+            view.mark_all_dirty();
+        }
+        for pane in &mut self.panes {
+            pane.viewport.mark_all_dirty();
+        }
+        self.resize(self.terminal_size);
+        self.set_status_message(&format!("Theme: {}", theme.name));
+    }
+
+    fn resize(&mut self, to: Size) {
+        // The terminal may have rewrapped what was on screen, so start over.
+        let _ = Terminal::clear_screen();
+        self.terminal_size = to;
+        self.layout_panes();
+        self.status_bar.resize(Size {
+            height: to.height.saturating_sub(1),
+            width: to.width,
+        });
+        self.message_bar.resize(to);
+    }
+
+    fn set_status_message(&mut self, message: &str) {
+        self.message_bar.update_message(message);
     }
 
     pub fn run(&mut self) {
         loop {
             self.refresh_screen();
             if self.should_quit {
+                for view_idx in 0..self.views.len() {
+                    self.remember_position(view_idx);
+                }
+                let _ = self.positions.save();
+                let _ = self.recent_files.save();
                 break;
             }
-            match read() {
+            match Terminal::read_event() {
                 Ok(event) => self.evaluate_event(event),
                 Err(err) => {
                     #[cfg(debug_assertions)]
@@ -63,39 +409,7 @@ impl Editor {
         }
     }
 
-    fn move_point(&mut self, key_code: KeyCode) {
-        let Location { mut x, mut y } = self.location;
-        let Size { height, width } = Terminal::size().unwrap_or_default();
-        match key_code {
-            KeyCode::Up => {
-                y = y.saturating_sub(1);
-            }
-            KeyCode::Down => {
-                y = min(y.saturating_add(1), height.saturating_sub(1));
-            }
-            KeyCode::Left => {
-                x = x.saturating_sub(1);
-            }
-            KeyCode::Right => {
-                x = min(x.saturating_add(1), width.saturating_sub(1));
-            }
-            KeyCode::End => {
-                x = width.saturating_sub(1);
-            }
-            KeyCode::Home => {
-                x = 0;
-            }
-            KeyCode::PageUp => {
-                y = 0;
-            }
-            KeyCode::PageDown => {
-                y = height.saturating_sub(1);
-            }
-            _ => (),
-        }
-        self.location = Location { x, y };
-    }
-
+    #[allow(clippy::needless_pass_by_value)]
     fn evaluate_event(&mut self, event: Event) {
         match event {
             Event::Key(KeyEvent {
@@ -103,25 +417,7 @@ impl Editor {
                 kind: KeyEventKind::Press,
                 modifiers,
                 ..
-            }) => match (code, modifiers) {
-                (KeyCode::Char('q'), KeyModifiers::CONTROL) => {
-                    self.should_quit = true;
-                }
-                (
-                    KeyCode::Up
-                    | KeyCode::Down
-                    | KeyCode::Left
-                    | KeyCode::Right
-                    | KeyCode::End
-                    | KeyCode::Home
-                    | KeyCode::PageUp
-                    | KeyCode::PageDown,
-                    _,
-                ) => {
-                    self.move_point(code);
-                }
-                _ => {}
-            },
+            }) => self.evaluate_key(code, modifiers),
             Event::Resize(width_u16, height_u16) => {
                 #[allow(clippy::as_conversions)]
                 let height = height_u16 as usize;
@@ -129,21 +425,1141 @@ impl Editor {
                 #[allow(clippy::as_conversions)]
                 let width = width_u16 as usize;
 
-                self.view.resize(Size { height, width });
+                self.resize(Size { height, width });
+            }
+            Event::Paste(text) => self.paste_text(&text),
+            Event::Mouse(MouseEvent {
+                kind: MouseEventKind::Down(MouseButton::Left),
+                column,
+                row,
+                ..
+            }) => {
+                #[allow(clippy::as_conversions)]
+                let at = Position {
+                    col: column as usize,
+                    row: row as usize,
+                };
+                self.click(at);
+            }
+            Event::Mouse(MouseEvent {
+                kind: kind @ (MouseEventKind::ScrollUp | MouseEventKind::ScrollDown),
+                row,
+                ..
+            }) => {
+                #[allow(clippy::as_conversions)]
+                let row = row as usize;
+                // The wheel scrolls the pane under the pointer without focusing it.
+                if let Some(pane_idx) = self.pane_at(row) {
+                    if kind == MouseEventKind::ScrollUp {
+                        self.with_pane(pane_idx, View::scroll_up);
+                    } else {
+                        self.with_pane(pane_idx, View::scroll_down);
+                    }
+                }
             }
             _ => {}
         }
     }
 
+    fn evaluate_key(&mut self, code: KeyCode, modifiers: KeyModifiers) {
+        if (code, modifiers) == (KeyCode::Char('q'), KeyModifiers::CONTROL) {
+            self.handle_quit();
+            return;
+        }
+        self.quit_times = self.config.quit_times;
+        if !Self::keeps_extra_carets(code, modifiers) {
+            self.view_mut().clear_extra_carets();
+        }
+        if self.mode == Some(Mode::Normal) && self.evaluate_normal_key(code, modifiers) {
+            return;
+        }
+        self.record_insert_key(code, modifiers);
+        if Self::is_edit_key(code, modifiers) && !self.ensure_writable() {
+            return;
+        }
+        if self.evaluate_window_key(code, modifiers)
+            || self.evaluate_clipboard_key(code, modifiers)
+            || self.evaluate_line_key(code, modifiers)
+        {
+            return;
+        }
+        match (code, modifiers) {
+            (KeyCode::Char('s'), KeyModifiers::CONTROL) => {
+                self.save();
+            }
+            (KeyCode::Char('f'), KeyModifiers::CONTROL) => {
+                self.search();
+            }
+            (KeyCode::Char('r'), KeyModifiers::CONTROL) => {
+                self.replace();
+            }
+            (KeyCode::Char('g'), KeyModifiers::CONTROL) => {
+                self.go_to_line();
+            }
+            (KeyCode::Char('e'), KeyModifiers::CONTROL) => {
+                self.execute_command();
+            }
+            (KeyCode::Left, KeyModifiers::ALT) => {
+                if !self.view_mut().jump_back() {
+                    self.set_status_message("No earlier jump.");
+                }
+            }
+            (KeyCode::Right, KeyModifiers::ALT) => {
+                if !self.view_mut().jump_forward() {
+                    self.set_status_message("No later jump.");
+                }
+            }
+            (KeyCode::Char('m'), KeyModifiers::ALT) => {
+                self.view_mut().jump_to_matching_bracket();
+            }
+            (KeyCode::Char('j'), KeyModifiers::ALT) => {
+                self.view_mut().add_caret_below();
+            }
+            (KeyCode::Char('n'), KeyModifiers::ALT) => {
+                if !self.view_mut().add_caret_at_next_match() {
+                    self.set_status_message("No other occurrence of this word.");
+                }
+            }
+            (KeyCode::Char(' '), KeyModifiers::CONTROL) => {
+                self.view_mut().toggle_selection();
+            }
+            (KeyCode::Esc, _) => {
+                self.view_mut().clear_selection();
+                if self.mode == Some(Mode::Insert) {
+                    self.leave_insert_mode();
+                }
+            }
+            (KeyCode::Char('z'), KeyModifiers::CONTROL) => {
+                self.view_mut().undo();
+            }
+            (KeyCode::Char('y'), KeyModifiers::CONTROL) => {
+                self.view_mut().redo();
+            }
+            (KeyCode::Char('z' | 'Z'), modifiers)
+                if modifiers == KeyModifiers::CONTROL | KeyModifiers::SHIFT =>
+            {
+                self.view_mut().redo();
+            }
+            (KeyCode::Char(character), KeyModifiers::NONE | KeyModifiers::SHIFT) => {
+                self.view_mut().insert_char(character);
+            }
+            (KeyCode::Tab, KeyModifiers::NONE) => {
+                self.view_mut().insert_tab();
+            }
+            (KeyCode::Enter, _) => {
+                self.view_mut().insert_newline();
+            }
+            (KeyCode::Delete, _) => {
+                self.view_mut().delete();
+            }
+            (KeyCode::Backspace, _) => {
+                self.view_mut().backspace();
+            }
+            _ => {
+                if let Ok(direction) = Move::try_from((code, modifiers)) {
+                    self.view_mut().move_caret(direction);
+                }
+            }
+        }
+    }
+
+    /// Handles the keys that rework whole lines or words in place, returning
+    /// whether `code` was one of them.
+    fn evaluate_line_key(&mut self, code: KeyCode, modifiers: KeyModifiers) -> bool {
+        match (code, modifiers) {
+            (KeyCode::Char('c'), KeyModifiers::ALT) => {
+                self.change_case();
+            }
+            (KeyCode::Char('d'), KeyModifiers::CONTROL) => {
+                self.view_mut().duplicate_line();
+            }
+            (KeyCode::Char(';'), KeyModifiers::ALT) => {
+                if !self.view_mut().toggle_comment() {
+                    self.set_status_message("ERR: This file type has no line comments.");
+                }
+            }
+            (KeyCode::Up, KeyModifiers::ALT) => {
+                self.view_mut().move_line(SearchDirection::Backward);
+            }
+            (KeyCode::Down, KeyModifiers::ALT) => {
+                self.view_mut().move_line(SearchDirection::Forward);
+            }
+            _ => return false,
+        }
+        true
+    }
+
+    /// Handles the keys that copy, cut and paste, returning whether `code`
+    /// was one of them.
+    fn evaluate_clipboard_key(&mut self, code: KeyCode, modifiers: KeyModifiers) -> bool {
+        match (code, modifiers) {
+            (KeyCode::Char('w'), KeyModifiers::ALT) => {
+                if let Some(text) = self.view().copy_selection() {
+                    self.clipboard.copy(&text);
+                    self.view_mut().clear_selection();
+                }
+            }
+            (KeyCode::Char('k'), KeyModifiers::CONTROL) => {
+                let view = self.view_mut();
+                if let Some(text) = view.cut_selection().or_else(|| view.cut_line()) {
+                    self.clipboard.copy(&text);
+                }
+            }
+            (KeyCode::Char('u'), KeyModifiers::CONTROL) => {
+                if let Some(text) = self.clipboard.paste() {
+                    self.view_mut().paste(&text);
+                }
+            }
+            (KeyCode::Char('k'), KeyModifiers::ALT) => {
+                if let Some(text) = self.view_mut().kill_to_line_end() {
+                    self.clipboard.copy(&text);
+                }
+            }
+            (KeyCode::Char('u'), KeyModifiers::ALT) => {
+                if let Some(text) = self.view_mut().kill_to_line_start() {
+                    self.clipboard.copy(&text);
+                }
+            }
+            _ => return false,
+        }
+        true
+    }
+
+    fn set_mode(&mut self, mode: Mode) {
+        self.mode = Some(mode);
+        let _ = Terminal::set_caret_style(match mode {
+            Mode::Normal => CaretStyle::Block,
+            Mode::Insert => CaretStyle::Bar,
+        });
+    }
+
+    /// Handles a key in Normal mode, returning whether it was a Normal mode
+    /// command. Plain keys never type text here; everything else falls
+    /// through to the regular bindings.
+    fn evaluate_normal_key(&mut self, code: KeyCode, modifiers: KeyModifiers) -> bool {
+        let pending_key = self.pending_key.take();
+        let pending_count = self.pending_count.take();
+        let character = match (code, modifiers) {
+            (KeyCode::Char(character), KeyModifiers::NONE | KeyModifiers::SHIFT) => character,
+            (KeyCode::Enter, _) => 'j',
+            (KeyCode::Backspace, _) => 'h',
+            (KeyCode::Delete, _) => 'x',
+            (KeyCode::Tab, _) => return true,
+            _ => return false,
+        };
+        if let Some(mark_key @ ('m' | '\'' | '`')) = pending_key {
+            self.evaluate_mark_key(mark_key, character);
+            return true;
+        }
+        if pending_key == Some('z') && character == '=' {
+            self.suggest_spelling();
+            return true;
+        }
+        if pending_key == Some('z') {
+            let placement = match character {
+                't' => Some(CaretPlacement::Top),
+                'z' => Some(CaretPlacement::Center),
+                'b' => Some(CaretPlacement::Bottom),
+                _ => None,
+            };
+            if let Some(placement) = placement {
+                self.view_mut().place_caret_line(placement);
+            }
+            return true;
+        }
+        if let Some(digit) = character.to_digit(10)
+            && (digit != 0 || pending_count.is_some())
+        {
+            let digit = usize::try_from(digit).unwrap_or_default();
+            self.pending_count = Some(
+                pending_count
+                    .unwrap_or_default()
+                    .saturating_mul(10)
+                    .saturating_add(digit),
+            );
+            self.pending_key = pending_key;
+            return true;
+        }
+        let count = pending_count.unwrap_or(1);
+        let direction = match character {
+            'h' => Some(Move::Left),
+            'j' => Some(Move::Down),
+            'k' => Some(Move::Up),
+            'l' => Some(Move::Right),
+            '0' => Some(Move::StartOfLine),
+            '$' => Some(Move::EndOfLine),
+            _ => None,
+        };
+        if let Some(direction) = direction {
+            for _ in 0..count {
+                self.view_mut().move_caret(direction);
+            }
+            return true;
+        }
+        match character {
+            '%' => self.view_mut().jump_to_matching_bracket(),
+            ':' => self.execute_command(),
+            'i' => self.enter_insert_mode(false),
+            'a' => self.enter_insert_mode(true),
+            'z' => self.pending_key = Some('z'),
+            _ => self.evaluate_normal_edit(character, pending_key, pending_count),
+        }
+        true
+    }
+
+    /// Handles the Normal mode keys that change the buffer, remembering the
+    /// edit for `.`.
+    fn evaluate_normal_edit(
+        &mut self,
+        character: char,
+        pending_key: Option<char>,
+        pending_count: Option<usize>,
+    ) {
+        let count = pending_count.unwrap_or(1);
+        let edit = match character {
+            'x' => RepeatableEdit::DeleteChars(count),
+            'J' => RepeatableEdit::JoinLines,
+            'd' if pending_key == Some('d') => RepeatableEdit::CutLines(count),
+            'd' => {
+                self.pending_key = Some('d');
+                self.pending_count = pending_count;
+                return;
+            }
+            'm' | '\'' | '`' => {
+                self.pending_key = Some(character);
+                return;
+            }
+            // A count given to `.` replaces the one the edit was made with.
+            '.' => match (self.last_edit.clone(), pending_count) {
+                (Some(RepeatableEdit::DeleteChars(_)), Some(count)) => {
+                    RepeatableEdit::DeleteChars(count)
+                }
+                (Some(RepeatableEdit::CutLines(_)), Some(count)) => RepeatableEdit::CutLines(count),
+                (Some(edit), _) => edit,
+                (None, _) => return,
+            },
+            _ => return,
+        };
+        if !self.ensure_writable() {
+            return;
+        }
+        match &edit {
+            RepeatableEdit::Insert { after_caret, text } => {
+                if *after_caret {
+                    self.view_mut().move_caret(Move::Right);
+                }
+                self.view_mut().retype(text);
+                self.view_mut().move_caret(Move::Left);
+            }
+            RepeatableEdit::DeleteChars(count) => self.view_mut().delete_chars(*count),
+            RepeatableEdit::CutLines(count) => {
+                if let Some(text) = self.view_mut().cut_lines(*count) {
+                    self.clipboard.copy(&text);
+                }
+            }
+            RepeatableEdit::JoinLines => self.view_mut().join_lines(),
+        }
+        self.last_edit = Some(edit);
+    }
+
+    /// Sets mark `name` at the caret for `m`, or jumps to it for the others.
+    fn evaluate_mark_key(&mut self, mark_key: char, name: char) {
+        if !name.is_ascii_lowercase() {
+            self.set_status_message("ERR: Marks are named a to z.");
+        } else if mark_key == 'm' {
+            self.view_mut().set_mark(name);
+            self.set_status_message(&format!("Mark {name} set."));
+        } else if !self.view_mut().jump_to_mark(name) {
+            self.set_status_message(&format!("ERR: Mark {name} is not set."));
+        }
+    }
+
+    fn enter_insert_mode(&mut self, after_caret: bool) {
+        if after_caret {
+            self.view_mut().move_caret(Move::Right);
+        }
+        self.set_mode(Mode::Insert);
+        self.insert_session = Some(RepeatableEdit::Insert {
+            after_caret,
+            text: String::new(),
+        });
+    }
+
+    fn leave_insert_mode(&mut self) {
+        // Like vi, leave the caret on the last character typed.
+        self.view_mut().move_caret(Move::Left);
+        self.set_mode(Mode::Normal);
+        if let Some(edit) = self.insert_session.take()
+            && !matches!(&edit, RepeatableEdit::Insert { text, .. } if text.is_empty())
+        {
+            self.last_edit = Some(edit);
+        }
+    }
+
+    /// Adds a key typed in Insert mode to the text `.` will retype. Keys
+    /// that do more than type, like moving the caret, start the text over,
+    /// since only what follows them would type the same again.
+    fn record_insert_key(&mut self, code: KeyCode, modifiers: KeyModifiers) {
+        let Some(RepeatableEdit::Insert { text, .. }) = &mut self.insert_session else {
+            return;
+        };
+        match (code, modifiers) {
+            (KeyCode::Char(character), KeyModifiers::NONE | KeyModifiers::SHIFT) => {
+                text.push(character);
+            }
+            (KeyCode::Enter, _) => text.push('\n'),
+            (KeyCode::Tab, KeyModifiers::NONE) => text.push('\t'),
+            (KeyCode::Backspace, _) if text.pop().is_some() => {}
+            (KeyCode::Esc, _) => {}
+            _ => text.clear(),
+        }
+    }
+
+    /// Handles the keys that switch buffers, arrange panes or change how the
+    /// buffer is displayed. Returns whether `code` was one of them.
+    fn evaluate_window_key(&mut self, code: KeyCode, modifiers: KeyModifiers) -> bool {
+        match (code, modifiers) {
+            (KeyCode::Char('n'), KeyModifiers::CONTROL) => {
+                self.switch_view(true);
+            }
+            (KeyCode::Char('p'), KeyModifiers::CONTROL) => {
+                self.switch_view(false);
+            }
+            (KeyCode::Char('s'), KeyModifiers::ALT) => {
+                self.split_pane();
+            }
+            (KeyCode::Char('o'), KeyModifiers::ALT) => {
+                self.focus_next_pane();
+            }
+            (KeyCode::Char('x'), KeyModifiers::ALT) => {
+                self.close_pane();
+            }
+            (KeyCode::Char('t'), KeyModifiers::ALT) => {
+                self.cycle_theme();
+            }
+            (KeyCode::Char('r'), KeyModifiers::ALT) => {
+                self.view_mut().toggle_read_only();
+            }
+            (KeyCode::Char('z'), KeyModifiers::ALT) => {
+                self.view_mut().toggle_soft_wrap();
+            }
+            (KeyCode::Char('f'), KeyModifiers::ALT) => {
+                if !self.view_mut().toggle_fold() {
+                    self.set_status_message("Nothing to fold here.");
+                }
+            }
+            (KeyCode::Char('t'), KeyModifiers::CONTROL) => {
+                self.view_mut().toggle_whitespace();
+            }
+            (KeyCode::Char('l'), KeyModifiers::CONTROL) => {
+                self.view_mut().cycle_line_numbers();
+            }
+            _ => return false,
+        }
+        true
+    }
+
+    /// Whether `code` types, deletes, pastes or moves at every caret, or adds
+    /// one. Any other key, Esc included, first goes back to a single caret.
+    const fn keeps_extra_carets(code: KeyCode, modifiers: KeyModifiers) -> bool {
+        match code {
+            KeyCode::Char('j' | 'n') if modifiers.bits() == KeyModifiers::ALT.bits() => true,
+            KeyCode::Char('u') => modifiers.bits() == KeyModifiers::CONTROL.bits(),
+            KeyCode::Char(_) => {
+                modifiers.is_empty() || modifiers.bits() == KeyModifiers::SHIFT.bits()
+            }
+            KeyCode::Up | KeyCode::Down => !modifiers.contains(KeyModifiers::ALT),
+            KeyCode::Enter
+            | KeyCode::Delete
+            | KeyCode::Backspace
+            | KeyCode::Tab
+            | KeyCode::Left
+            | KeyCode::Right
+            | KeyCode::Home
+            | KeyCode::End
+            | KeyCode::PageUp
+            | KeyCode::PageDown => true,
+            _ => false,
+        }
+    }
+
+    const fn is_edit_key(code: KeyCode, modifiers: KeyModifiers) -> bool {
+        match code {
+            KeyCode::Char('k' | 'u') => {
+                modifiers.contains(KeyModifiers::CONTROL) || modifiers.contains(KeyModifiers::ALT)
+            }
+            KeyCode::Char('r' | 'z' | 'y' | 'd') => modifiers.contains(KeyModifiers::CONTROL),
+            KeyCode::Char('c' | ';') | KeyCode::Up | KeyCode::Down => {
+                modifiers.contains(KeyModifiers::ALT)
+            }
+            KeyCode::Char(_) => {
+                modifiers.is_empty() || modifiers.bits() == KeyModifiers::SHIFT.bits()
+            }
+            KeyCode::Enter | KeyCode::Delete | KeyCode::Backspace | KeyCode::Tab => true,
+            _ => false,
+        }
+    }
+
+    /// Whether the buffer may be edited, telling the user why not otherwise.
+    fn ensure_writable(&mut self) -> bool {
+        if !self.view().is_read_only() {
+            return true;
+        }
+        if self.view().is_lazy() {
+            self.offer_full_load();
+        } else {
+            self.set_status_message("Buffer is read-only.");
+        }
+        !self.view().is_read_only()
+    }
+
+    /// Inserts text pasted into the terminal as a single edit.
+    fn paste_text(&mut self, text: &str) {
+        if self.ensure_writable() {
+            let text = text.replace("\r\n", "\n").replace('\r', "\n");
+            self.view_mut().paste(&text);
+            if let Some(RepeatableEdit::Insert { text, .. }) = &mut self.insert_session {
+                text.clear();
+            }
+        }
+    }
+
+    fn change_case(&mut self) {
+        let choice = self
+            .read_choice("Change case to (u)pper, (l)ower or (t)itle?")
+            .ok()
+            .flatten();
+        let kind = match choice {
+            Some('u') => CaseTransform::Upper,
+            Some('l') => CaseTransform::Lower,
+            Some('t') => CaseTransform::Title,
+            _ => {
+                self.set_status_message("");
+                return;
+            }
+        };
+        self.view_mut().transform_case(kind);
+        self.set_status_message("");
+    }
+
+    fn offer_full_load(&mut self) {
+        let choice = self
+            .read_choice("This large file is read-only. Load all of it to edit? (y/n)")
+            .ok()
+            .flatten();
+        if choice != Some('y') {
+            self.set_status_message("");
+            return;
+        }
+        if self.view_mut().load_fully().is_ok() {
+            self.set_status_message("File fully loaded.");
+        } else {
+            self.set_status_message("Error loading file!");
+        }
+    }
+
+    fn save(&mut self) {
+        if self.view().is_read_only() {
+            self.set_status_message("Buffer is read-only.");
+            return;
+        }
+        let result = if self.view().has_file_name() {
+            if self.view().changed_on_disk() && !self.confirm_overwrite() {
+                return;
+            }
+            self.view_mut().save()
+        } else {
+            match self.prompt("Save as: ") {
+                Ok(Some(file_name)) if !file_name.is_empty() => self.view_mut().save_as(&file_name),
+                Ok(_) => {
+                    self.set_status_message("Save aborted.");
+                    return;
+                }
+                Err(err) => Err(err),
+            }
+        };
+        match result {
+            Ok(()) => self.set_status_message("File saved successfully."),
+            Err(err) => self.set_status_message(&format!("Error writing file: {err}")),
+        }
+    }
+
+    /// Asks what to do about a file another program changed since it was
+    /// loaded. Returns whether to save over it anyway.
+    fn confirm_overwrite(&mut self) -> bool {
+        let choice = self
+            .read_choice("File changed on disk. Overwrite it? (y/n, r = reload)")
+            .ok()
+            .flatten();
+        match choice {
+            Some('y') => true,
+            Some('r') => {
+                self.reload_file();
+                false
+            }
+            _ => {
+                self.set_status_message("Save aborted.");
+                false
+            }
+        }
+    }
+
+    /// Reloads the file from disk, asking first if that would lose edits.
+    fn confirm_reload(&mut self) {
+        if !self.view().has_file_name() {
+            self.set_status_message("ERR: The buffer has no file to reload.");
+            return;
+        }
+        if self.view().is_dirty() {
+            let choice = self
+                .read_choice("Discard unsaved changes and reload? (y/n)")
+                .ok()
+                .flatten();
+            if choice != Some('y') {
+                self.set_status_message("");
+                return;
+            }
+        }
+        self.reload_file();
+    }
+
+    fn reload_file(&mut self) {
+        match self.view_mut().reload() {
+            Ok(()) => self.set_status_message("File reloaded."),
+            Err(err) => self.set_status_message(&format!("Error reloading file: {err}")),
+        }
+    }
+
+    fn search(&mut self) {
+        let original_caret = self.view().caret();
+        let original_offset = self.view().scroll_offset();
+        let query = self.prompt_with_callback(
+            "Search (Esc to cancel, Arrows to navigate): ",
+            |editor, code, input| {
+                let query = match Query::parse(input) {
+                    Ok(query) => query,
+                    Err(message) => {
+                        editor.view_mut().clear_search_highlight();
+                        return Some(format!("Search ({message}): "));
+                    }
+                };
+                let query = &query;
+                let wrap = editor.config.search_wrap;
+                let view = editor.view_mut();
+                let caret = view.caret();
+                let (from, direction) = match code {
+                    KeyCode::Right | KeyCode::Down => (
+                        Location {
+                            x: caret.x.saturating_add(1),
+                            y: caret.y,
+                        },
+                        SearchDirection::Forward,
+                    ),
+                    KeyCode::Left | KeyCode::Up => (caret, SearchDirection::Backward),
+                    _ => (caret, SearchDirection::Forward),
+                };
+                let found = if wrap {
+                    view.find_wrapping(query, from, direction)
+                } else {
+                    view.find(query, from, direction)
+                        .map(|found| (found, false))
+                };
+                if let Some((found, _)) = found {
+                    view.set_caret(found);
+                }
+                view.set_search_highlight(query, view.caret());
+                let flags = query.flags();
+                if query.is_empty() {
+                    return Some(format!(
+                        "Search{flags} (Esc to cancel, Arrows to navigate): "
+                    ));
+                }
+                let (before, total) = view.count_matches(query, view.caret());
+                let prompt = if total == 0 {
+                    format!("Search{flags} (no matches): ")
+                } else if let Some((_, wrapped)) = found {
+                    format!(
+                        "Search{flags} (match {}/{total}{}): ",
+                        before.saturating_add(1),
+                        if wrapped { ", search wrapped" } else { "" }
+                    )
+                } else {
+                    format!("Search{flags} (no more of {total} matches): ")
+                };
+                Some(prompt)
+            },
+        );
+        self.view_mut().clear_search_highlight();
+        if !matches!(query, Ok(Some(_))) {
+            self.view_mut().set_caret(original_caret);
+            self.view_mut().set_scroll_offset(original_offset);
+        } else if self.view().caret() != original_caret {
+            self.view_mut().push_jump(original_caret);
+        }
+    }
+
+    fn replace(&mut self) {
+        let Ok(Some(input)) = self.prompt("Replace: ") else {
+            return;
+        };
+        let query = match Query::parse(&input) {
+            Ok(query) if !query.is_empty() => query,
+            Ok(_) => return,
+            Err(message) => {
+                self.set_status_message(&format!("ERR: {message}"));
+                return;
+            }
+        };
+        let Ok(Some(replacement)) = self.prompt(&format!("Replace {} with: ", query.text)) else {
+            return;
+        };
+        let mut from = self.view().caret();
+        let mut replace_all = false;
+        let mut replaced: usize = 0;
+        while let Some(found) = self.view_mut().find(&query, from, SearchDirection::Forward) {
+            self.view_mut().set_caret(found);
+            self.view_mut().set_search_highlight(&query, found);
+            let choice = if replace_all {
+                Some('a')
+            } else {
+                self.read_choice("Replace this occurrence? (y/n/a/q)")
+                    .ok()
+                    .flatten()
+            };
+            match choice {
+                Some('y' | 'a') => {
+                    replace_all = choice == Some('a');
+                    from = if let Some(re) = query.regex()
+                        && let Some(regex_match) =
+                            self.view().find_regex(re, found, SearchDirection::Forward)
+                    {
+                        self.view_mut()
+                            .replace_regex_match(&regex_match, &replacement)
+                    } else {
+                        self.view_mut().replace_match(found, &query, &replacement)
+                    };
+                    replaced = replaced.saturating_add(1);
+                }
+                Some('n') => {
+                    from = Location {
+                        x: found.x.saturating_add(1),
+                        y: found.y,
+                    };
+                }
+                _ => break,
+            }
+        }
+        self.view_mut().clear_search_highlight();
+        self.set_status_message(&format!("Replaced {replaced} occurrence(s)."));
+    }
+
+    /// Waits for a single character answer, returning `None` on Esc.
+    fn read_choice(&mut self, prompt: &str) -> Result<Option<char>, Error> {
+        loop {
+            self.set_status_message(prompt);
+            self.view_mut().scroll_into_view();
+            self.render_frame(self.message_bar.caret_position());
+            match Terminal::read_event()? {
+                Event::Key(KeyEvent {
+                    code,
+                    kind: KeyEventKind::Press,
+                    ..
+                }) => match code {
+                    KeyCode::Esc => return Ok(None),
+                    KeyCode::Char(character) => return Ok(Some(character.to_ascii_lowercase())),
+                    _ => {}
+                },
+                Event::Paste(_) => {}
+                event => self.evaluate_event(event),
+            }
+        }
+    }
+
+    /// Lets the user step through `items` with the arrow keys and returns the
+    /// index of the one chosen with Enter, or `None` on Esc.
+    fn pick(&mut self, prompt: &str, items: &[String]) -> Result<Option<usize>, Error> {
+        let count = items.len();
+        let mut selected: usize = 0;
+        loop {
+            self.set_status_message(&format!(
+                "{prompt} ({}/{count}): {}",
+                selected.saturating_add(1),
+                items[selected]
+            ));
+            self.view_mut().scroll_into_view();
+            self.render_frame(self.message_bar.caret_position());
+            match Terminal::read_event()? {
+                Event::Key(KeyEvent {
+                    code,
+                    kind: KeyEventKind::Press,
+                    ..
+                }) => match code {
+                    KeyCode::Esc => return Ok(None),
+                    KeyCode::Enter => return Ok(Some(selected)),
+                    KeyCode::Down | KeyCode::Right | KeyCode::Tab => {
+                        selected = selected
+                            .saturating_add(1)
+                            .checked_rem(count)
+                            .unwrap_or_default();
+                    }
+                    KeyCode::Up | KeyCode::Left => {
+                        selected = selected
+                            .saturating_add(count)
+                            .saturating_sub(1)
+                            .checked_rem(count)
+                            .unwrap_or_default();
+                    }
+                    _ => {}
+                },
+                Event::Paste(_) => {}
+                event => self.evaluate_event(event),
+            }
+        }
+    }
+
+    fn go_to_line(&mut self) {
+        match self.prompt("Go to line: ") {
+            Ok(Some(input)) => {
+                if let Ok(line_number) = input.trim().parse::<usize>() {
+                    self.view_mut().go_to_line(line_number);
+                    self.set_status_message("");
+                } else {
+                    self.set_status_message(&format!("Invalid line number: {input}"));
+                }
+            }
+            Ok(None) => self.set_status_message(""),
+            Err(_) => {}
+        }
+    }
+
+    /// Reads a command line and runs it.
+    fn execute_command(&mut self) {
+        let Ok(Some(input)) = self.prompt(":") else {
+            return;
+        };
+        if input.trim().is_empty() {
+            return;
+        }
+        match Command::try_from(input.as_str()) {
+            Ok(Command::Write) => self.save(),
+            Ok(Command::Quit) => self.quit(false),
+            Ok(Command::ForceQuit) => self.quit(true),
+            Ok(Command::WriteQuit) => {
+                self.save();
+                if !self.view().is_dirty() {
+                    self.quit(false);
+                }
+            }
+            Ok(Command::Edit(file_name)) => self.open_file(&file_name),
+            Ok(Command::Reload) => self.confirm_reload(),
+            Ok(Command::Recent) => self.open_recent_file(),
+            Ok(Command::Set(option)) => self.set_option(&option),
+            Ok(Command::Stats) => self.show_stats(),
+            Ok(Command::GoTo(line_number)) => self.view_mut().go_to_line(line_number),
+            Ok(Command::Earlier) => self.travel_history(false),
+            Ok(Command::Later) => self.travel_history(true),
+            Ok(Command::UndoList) => self.show_undo_list(),
+            Ok(Command::Join) => {
+                if self.ensure_writable() {
+                    self.view_mut().join_lines();
+                }
+            }
+            Ok(Command::Mark(name)) => self.evaluate_mark_key('m', name),
+            Ok(Command::JumpToMark(name)) => self.evaluate_mark_key('\'', name),
+            Ok(Command::Suggest) => self.suggest_spelling(),
+            Err(()) => self.set_status_message(&format!("ERR: Unknown command: {input}")),
+        }
+    }
+
+    fn show_stats(&mut self) {
+        if self.view().is_lazy() {
+            self.set_status_message("ERR: Only part of the file is loaded.");
+            return;
+        }
+        let stats = self.view().stats();
+        self.set_status_message(&format!(
+            "{} lines, {} words, {} characters, {} bytes",
+            stats.lines, stats.words, stats.characters, stats.bytes
+        ));
+    }
+
+    fn travel_history(&mut self, later: bool) {
+        if self.ensure_writable() {
+            self.view_mut().travel_history(later);
+        }
+    }
+
+    fn show_undo_list(&mut self) {
+        let list = self.view().undo_list();
+        let branch_points: Vec<String> = list
+            .branch_points
+            .iter()
+            .map(|point| format!("{} ({} ways)", point.change, point.branches))
+            .collect();
+        let branches = if branch_points.is_empty() {
+            String::from("no branches")
+        } else {
+            format!("branches at {}", branch_points.join(", "))
+        };
+        self.set_status_message(&format!(
+            "Change {} of {}; {branches}",
+            list.current, list.newest
+        ));
+    }
+
+    /// Lets the user pick one of the recently opened files that still exist
+    /// and opens it.
+    fn open_recent_file(&mut self) {
+        let file_names: Vec<String> = self
+            .recent_files
+            .list()
+            .into_iter()
+            .filter(|path| path.exists())
+            .filter_map(|path| path.to_str().map(String::from))
+            .collect();
+        if file_names.is_empty() {
+            self.set_status_message("No recent files.");
+            return;
+        }
+        match self.pick("Open recent", &file_names) {
+            Ok(Some(idx)) => {
+                self.set_status_message("");
+                self.open_file(&file_names[idx]);
+            }
+            Ok(None) => self.set_status_message(""),
+            Err(_) => {}
+        }
+    }
+
+    /// Applies a `:set` option to the focused buffer. Options the config file
+    /// also knows are remembered for buffers opened later in the session.
+    fn set_option(&mut self, option: &str) {
+        let setting = match Setting::try_from(option) {
+            Ok(setting) => setting,
+            Err(message) => {
+                self.set_status_message(&format!("ERR: {message}"));
+                return;
+            }
+        };
+        let mut options = self.view().options();
+        match setting {
+            Setting::TabWidth(tab_width) => {
+                self.config.tab_width = tab_width;
+                options.tab_width = tab_width;
+                self.view_mut().set_options(options);
+            }
+            Setting::ExpandTab(expand_tab) => {
+                self.config.expand_tab = expand_tab;
+                options.expand_tab = expand_tab;
+                self.view_mut().set_options(options);
+            }
+            Setting::Number(show_line_numbers) => {
+                self.config.show_line_numbers = show_line_numbers;
+                self.view_mut().set_line_numbers(if show_line_numbers {
+                    LineNumbers::Absolute
+                } else {
+                    LineNumbers::Off
+                });
+            }
+            Setting::ReadOnly(read_only) => self.view_mut().set_read_only(read_only),
+            Setting::Wrap(soft_wrap) => self.view_mut().set_soft_wrap(soft_wrap),
+            Setting::Spell(false) => {
+                self.config.spell_check = false;
+                self.view_mut().set_dictionary(None);
+            }
+            Setting::Spell(true) => match self.load_dictionary() {
+                Ok(dictionary) => {
+                    self.config.spell_check = true;
+                    self.view_mut().set_dictionary(Some(dictionary));
+                }
+                Err(message) => self.set_status_message(&message),
+            },
+        }
+    }
+
+    /// The dictionary named in the config, reading it if that hasn't been
+    /// done yet.
+    fn load_dictionary(&mut self) -> Result<Rc<Dictionary>, String> {
+        if let Some(dictionary) = &self.dictionary {
+            return Ok(Rc::clone(dictionary));
+        }
+        let dictionary = Dictionary::load(&self.config.dictionary).map_err(|err| {
+            format!(
+                "ERR: Could not read dictionary {}: {err}",
+                self.config.dictionary.display()
+            )
+        })?;
+        let dictionary = Rc::new(dictionary);
+        self.dictionary = Some(Rc::clone(&dictionary));
+        Ok(dictionary)
+    }
+
+    /// Swaps the word under the caret for the next spelling suggestion.
+    fn suggest_spelling(&mut self) {
+        if !self.view().spell_check() {
+            self.set_status_message("Spell checking is off (:set spell).");
+            return;
+        }
+        if !self.ensure_writable() {
+            return;
+        }
+        match self.view_mut().next_spelling_suggestion() {
+            Some((position, count)) if position == count => {
+                self.set_status_message("Back to the original spelling.");
+            }
+            Some((position, count)) => self.set_status_message(&format!(
+                "Suggestion {position} of {}.",
+                count.saturating_sub(1)
+            )),
+            None => self.set_status_message("No suggestions."),
+        }
+    }
+
+    /// Quits unless some buffer has unsaved changes and `force` is not set.
+    fn quit(&mut self, force: bool) {
+        if force || !self.views.iter().any(View::is_dirty) {
+            self.should_quit = true;
+        } else {
+            self.set_status_message("ERR: Unsaved changes (add ! to override).");
+        }
+    }
+
+    fn prompt(&mut self, prompt: &str) -> Result<Option<String>, Error> {
+        self.prompt_with_callback(prompt, |_, _, _| None)
+    }
+
+    /// Reads a line of input. `callback` sees every key along with the input
+    /// so far and may return a new prompt to show in front of it.
+    fn prompt_with_callback<F>(
+        &mut self,
+        prompt: &str,
+        mut callback: F,
+    ) -> Result<Option<String>, Error>
+    where
+        F: FnMut(&mut Self, KeyCode, &str) -> Option<String>,
+    {
+        let mut prompt = prompt.to_string();
+        let mut input = String::new();
+        loop {
+            self.set_status_message(&format!("{prompt}{input}"));
+            self.view_mut().scroll_into_view();
+            self.render_frame(self.message_bar.caret_position());
+            match Terminal::read_event()? {
+                Event::Key(KeyEvent {
+                    code,
+                    kind: KeyEventKind::Press,
+                    modifiers,
+                    ..
+                }) => {
+                    match code {
+                        KeyCode::Esc => {
+                            self.set_status_message("");
+                            return Ok(None);
+                        }
+                        KeyCode::Enter => {
+                            self.set_status_message("");
+                            return Ok(Some(input));
+                        }
+                        KeyCode::Backspace => {
+                            input.pop();
+                        }
+                        KeyCode::Char(character) if !modifiers.contains(KeyModifiers::CONTROL) => {
+                            input.push(character);
+                        }
+                        _ => {}
+                    }
+                    if let Some(new_prompt) = callback(self, code, &input) {
+                        prompt = new_prompt;
+                    }
+                }
+                Event::Paste(text) => {
+                    input.push_str(text.lines().next().unwrap_or_default());
+                }
+                event => self.evaluate_event(event),
+            }
+        }
+    }
+
+    fn handle_quit(&mut self) {
+        let dirty_count = self.views.iter().filter(|view| view.is_dirty()).count();
+        if dirty_count > 0 && self.quit_times > 0 {
+            let subject = if dirty_count == 1 && self.views.len() == 1 {
+                String::from("File has")
+            } else {
+                format!("{dirty_count} file(s) have")
+            };
+            self.set_status_message(&format!(
+                "WARNING! {subject} unsaved changes. Press Ctrl-Q {} more times to quit.",
+                self.quit_times
+            ));
+            self.quit_times = self.quit_times.saturating_sub(1);
+        } else {
+            self.should_quit = true;
+        }
+    }
+
     fn refresh_screen(&mut self) {
+        self.view_mut().scroll_into_view();
+        self.render_frame(self.view().caret_position());
+    }
+
+    fn render_frame(&mut self, caret: Position) {
+        let mut status = self.view().document_status();
+        status.buffer_index = self.panes[self.active_pane].view;
+        status.buffer_count = self.views.len();
+        status.mode = self.mode;
+        self.status_bar.update_status(status);
+        let active_view = self.panes[self.active_pane].view;
+        let edited = self.view().needs_redraw();
+        for (pane_idx, pane) in self.panes.iter_mut().enumerate() {
+            if pane_idx != self.active_pane && pane.view == active_view && edited {
+                pane.viewport.mark_all_dirty();
+            }
+        }
+        let panes_need_redraw = self.view().needs_redraw()
+            || self.panes.iter().enumerate().any(|(pane_idx, pane)| {
+                pane_idx != self.active_pane && pane.viewport.needs_redraw()
+            });
+        if !panes_need_redraw
+            && !self.status_bar.needs_redraw()
+            && !self.message_bar.needs_redraw()
+            && self.last_caret == Some(caret)
+        {
+            return;
+        }
         let _ = Terminal::hide_caret();
-        self.view.render();
-        let _ = Terminal::move_caret_to(Position {
-            col: self.location.x,
-            row: self.location.y,
-        });
+        for pane_idx in 0..self.panes.len() {
+            self.with_pane(pane_idx, View::render);
+        }
+        self.render_dividers();
+        self.status_bar.render();
+        self.message_bar.render();
+        let _ = Terminal::move_caret_to(caret);
         let _ = Terminal::show_caret();
         let _ = Terminal::execute();
+        self.last_caret = Some(caret);
+    }
+
+    /// Draws the row below each pane but the last, labelled with the name of
+    /// the buffer above it.
+    fn render_dividers(&self) {
+        let width = self.terminal_size.width;
+        let placements = pane::layout(self.panes.len(), self.text_area_size());
+        for (pane, (origin_row, size)) in self.panes.iter().zip(placements).rev().skip(1) {
+            let name = self.views[pane.view]
+                .document_status()
+                .file_name
+                .unwrap_or_else(|| String::from("[No Name]"));
+            let mut line: String = format!("── {name} ").chars().take(width).collect();
+            let padding = width.saturating_sub(line.chars().count());
+            line.push_str(&"─".repeat(padding));
+            let result = Terminal::print_colored_row(
+                origin_row.saturating_add(size.height),
+                &line,
+                Terminal::theme().divider,
+            );
+            debug_assert!(result.is_ok(), "Failed to render divider");
+        }
     }
 }
 
@@ -152,6 +1568,117 @@ impl Drop for Editor {
         let _ = Terminal::terminate();
         if self.should_quit {
             let _ = Terminal::print("Goodbye\r\n");
+            let _ = Terminal::execute();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::terminal::{Recorder, Size, Terminal};
+    use super::{Arguments, Config, Editor};
+    use core::cell::RefCell;
+    use crossterm::event::{Event, KeyCode, KeyEvent, KeyModifiers};
+    use std::rc::Rc;
+
+    const SIZE: Size = Size {
+        height: 8,
+        width: 30,
+    };
+
+    /// Runs an editor on `events` the way `Editor::run` would, and returns
+    /// the screen it leaves behind.
+    fn run_on(events: Vec<Event>) -> Vec<String> {
+        let output = Rc::new(RefCell::new(Vec::new()));
+        Terminal::set_target(Box::new(Recorder {
+            output: Rc::clone(&output),
+            events: events.into(),
+            size: SIZE,
+        }));
+        let mut editor = Editor::start(&Arguments::default(), Ok(Config::default())).unwrap();
+        loop {
+            editor.refresh_screen();
+            let Ok(event) = Terminal::read_event() else {
+                break;
+            };
+            editor.evaluate_event(event);
         }
+        Recorder::screen(&output.borrow(), SIZE)
+    }
+
+    fn key(code: KeyCode) -> Event {
+        Event::Key(KeyEvent::new(code, KeyModifiers::NONE))
+    }
+
+    fn typed(text: &str) -> Vec<Event> {
+        text.chars()
+            .map(|character| match character {
+                '\n' => key(KeyCode::Enter),
+                character => key(KeyCode::Char(character)),
+            })
+            .collect()
+    }
+
+    #[test]
+    fn typing_shows_up_on_screen() {
+        let screen = run_on(typed("hello\nworld"));
+        assert_eq!(screen[0], "hello");
+        assert_eq!(screen[1], "world");
+        assert_eq!(screen[2], "~");
+        assert!(screen[6].contains("[modified]"), "{screen:?}");
+    }
+
+    #[test]
+    fn command_line_reads_queued_events() {
+        let mut events = typed("a\nb\nc");
+        events.push(Event::Key(KeyEvent::new(
+            KeyCode::Char('e'),
+            KeyModifiers::CONTROL,
+        )));
+        events.extend(typed("1\nX"));
+        let screen = run_on(events);
+        assert_eq!(&screen[..3], ["Xa", "b", "c"]);
+    }
+
+    /// Starts an editor that renders into a recorder without running its
+    /// loop, and types `text` into it.
+    fn editor_with(text: &str) -> (Editor, Rc<RefCell<Vec<u8>>>) {
+        let output = Rc::new(RefCell::new(Vec::new()));
+        Terminal::set_target(Box::new(Recorder {
+            output: Rc::clone(&output),
+            events: Vec::new().into(),
+            size: SIZE,
+        }));
+        let mut editor = Editor::start(&Arguments::default(), Ok(Config::default())).unwrap();
+        editor.refresh_screen();
+        for event in typed(text) {
+            editor.evaluate_event(event);
+            editor.refresh_screen();
+        }
+        (editor, output)
+    }
+
+    /// What handling `event` writes to the screen.
+    fn output_for(editor: &mut Editor, output: &RefCell<Vec<u8>>, event: Event) -> String {
+        let before = output.borrow().len();
+        editor.evaluate_event(event);
+        editor.refresh_screen();
+        String::from_utf8_lossy(output.borrow().get(before..).unwrap_or_default()).into_owned()
+    }
+
+    #[test]
+    fn keys_that_change_nothing_write_nothing() {
+        let (mut editor, output) = editor_with("hello");
+        assert_eq!(output_for(&mut editor, &output, key(KeyCode::Up)), "");
+        assert_eq!(output_for(&mut editor, &output, key(KeyCode::F(12))), "");
+    }
+
+    #[test]
+    fn typing_a_character_writes_only_that_cell() {
+        let (mut editor, output) = editor_with("hello");
+        assert_eq!(
+            output_for(&mut editor, &output, key(KeyCode::Char('x'))),
+            "\x1b[?25l\x1b[1;6Hx\x1b[1;7H\x1b[?25h"
+        );
     }
 }
@@ -0,0 +1,95 @@
+use core::fmt::{self, Display};
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum AnnotationType {
+    Match,
+    SelectedMatch,
+    LineNumber,
+    Selection,
+    Keyword,
+    String,
+    Character,
+    Number,
+    Comment,
+    TrailingWhitespace,
+    MixedIndentation,
+    MatchingBracket,
+    UnmatchedBracket,
+    ColorColumn,
+    ExtraCaret,
+    ScrollbarThumb,
+    Fold,
+    /// A word the spell checker doesn't know, drawn underlined on top of
+    /// whatever colors it has.
+    Misspelled,
+}
+
+/// An annotation over the grapheme range `start..end` of a line.
+#[derive(Clone, Copy)]
+pub struct Annotation {
+    pub kind: AnnotationType,
+    pub start: usize,
+    pub end: usize,
+}
+
+impl Annotation {
+    pub const fn contains(&self, grapheme_idx: usize) -> bool {
+        self.start <= grapheme_idx && grapheme_idx < self.end
+    }
+}
+
+pub struct AnnotatedStringPart {
+    pub string: String,
+    pub kind: Option<AnnotationType>,
+    pub underlined: bool,
+}
+
+#[derive(Default)]
+pub struct AnnotatedString {
+    parts: Vec<AnnotatedStringPart>,
+}
+
+impl AnnotatedString {
+    pub fn push(&mut self, string: &str, kind: Option<AnnotationType>) {
+        self.push_underlined(string, kind, false);
+    }
+
+    pub fn push_underlined(
+        &mut self,
+        string: &str,
+        kind: Option<AnnotationType>,
+        underlined: bool,
+    ) {
+        if let Some(last) = self.parts.last_mut()
+            && last.kind == kind
+            && last.underlined == underlined
+        {
+            last.string.push_str(string);
+            return;
+        }
+        self.parts.push(AnnotatedStringPart {
+            string: string.to_string(),
+            kind,
+            underlined,
+        });
+    }
+
+    pub fn append(&mut self, other: Self) {
+        for part in other.parts {
+            self.push_underlined(&part.string, part.kind, part.underlined);
+        }
+    }
+
+    pub fn parts(&self) -> impl Iterator<Item = &AnnotatedStringPart> {
+        self.parts.iter()
+    }
+}
+
+impl Display for AnnotatedString {
+    fn fmt(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        for part in &self.parts {
+            write!(formatter, "{}", part.string)?;
+        }
+        Ok(())
+    }
+}
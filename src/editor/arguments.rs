@@ -0,0 +1,41 @@
+use super::terminal::CaretStyle;
+
+/// What the editor was asked to open, parsed from `rustamundo [--readonly] [--modal]
+/// [--caret=block|bar|underline] [+LINE] [FILE...]`.
+/// `+LINE` applies to the first file.
+#[derive(Default)]
+pub struct Arguments {
+    pub file_names: Vec<String>,
+    pub line_number: Option<usize>,
+    pub read_only: bool,
+    pub modal: bool,
+    pub caret_style: Option<CaretStyle>,
+    pub unknown: Vec<String>,
+}
+
+impl Arguments {
+    pub fn parse(args: impl Iterator<Item = String>) -> Self {
+        let mut arguments = Self::default();
+        for arg in args {
+            if arg == "--readonly" {
+                arguments.read_only = true;
+            } else if arg == "--modal" {
+                arguments.modal = true;
+            } else if let Some(style) = arg
+                .strip_prefix("--caret=")
+                .and_then(|name| CaretStyle::try_from(name).ok())
+            {
+                arguments.caret_style = Some(style);
+            } else if let Some(line_number) =
+                arg.strip_prefix('+').and_then(|number| number.parse().ok())
+            {
+                arguments.line_number = Some(line_number);
+            } else if arg.starts_with("--") {
+                arguments.unknown.push(arg);
+            } else {
+                arguments.file_names.push(arg);
+            }
+        }
+        arguments
+    }
+}
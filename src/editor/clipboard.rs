@@ -0,0 +1,35 @@
+/// Copies to the system clipboard when one is reachable, and always keeps an
+/// internal copy so cut and paste keep working without one (for example over
+/// SSH or in headless CI).
+pub struct Clipboard {
+    system: Option<arboard::Clipboard>,
+    internal: String,
+}
+
+impl Default for Clipboard {
+    fn default() -> Self {
+        Self {
+            system: arboard::Clipboard::new().ok(),
+            internal: String::new(),
+        }
+    }
+}
+
+impl Clipboard {
+    pub fn copy(&mut self, text: &str) {
+        self.internal = text.to_string();
+        if let Some(system) = &mut self.system {
+            let _ = system.set_text(text);
+        }
+    }
+
+    pub fn paste(&mut self) -> Option<String> {
+        let text = self
+            .system
+            .as_mut()
+            .and_then(|system| system.get_text().ok())
+            .filter(|text| !text.is_empty())
+            .map_or_else(|| self.internal.clone(), |text| text.replace("\r\n", "\n"));
+        (!text.is_empty()).then_some(text)
+    }
+}
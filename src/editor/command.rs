@@ -0,0 +1,161 @@
+use crossterm::event::{KeyCode, KeyModifiers};
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum Move {
+    Up,
+    Down,
+    Left,
+    Right,
+    WordLeft,
+    WordRight,
+    PageUp,
+    PageDown,
+    StartOfLine,
+    /// The first non-blank character of the line, or column 0 if the caret
+    /// is already there.
+    StartOfText,
+    EndOfLine,
+    StartOfDocument,
+    EndOfDocument,
+}
+
+impl Move {
+    pub const fn is_vertical(self) -> bool {
+        matches!(
+            self,
+            Self::Up
+                | Self::Down
+                | Self::PageUp
+                | Self::PageDown
+                | Self::StartOfDocument
+                | Self::EndOfDocument
+        )
+    }
+}
+
+impl TryFrom<(KeyCode, KeyModifiers)> for Move {
+    type Error = ();
+
+    fn try_from((code, modifiers): (KeyCode, KeyModifiers)) -> Result<Self, Self::Error> {
+        match code {
+            KeyCode::Left if modifiers == KeyModifiers::CONTROL => Ok(Self::WordLeft),
+            KeyCode::Right if modifiers == KeyModifiers::CONTROL => Ok(Self::WordRight),
+            KeyCode::Home if modifiers == KeyModifiers::CONTROL => Ok(Self::StartOfDocument),
+            KeyCode::End if modifiers == KeyModifiers::CONTROL => Ok(Self::EndOfDocument),
+            KeyCode::Up => Ok(Self::Up),
+            KeyCode::Down => Ok(Self::Down),
+            KeyCode::Left => Ok(Self::Left),
+            KeyCode::Right => Ok(Self::Right),
+            KeyCode::PageUp => Ok(Self::PageUp),
+            KeyCode::PageDown => Ok(Self::PageDown),
+            KeyCode::Home => Ok(Self::StartOfText),
+            KeyCode::End => Ok(Self::EndOfLine),
+            _ => Err(()),
+        }
+    }
+}
+
+/// A command typed on the `:` command line.
+#[derive(Clone, PartialEq, Eq)]
+pub enum Command {
+    Write,
+    Quit,
+    ForceQuit,
+    WriteQuit,
+    Edit(String),
+    Reload,
+    Recent,
+    Set(String),
+    Stats,
+    GoTo(usize),
+    Earlier,
+    Later,
+    UndoList,
+    Join,
+    Mark(char),
+    JumpToMark(char),
+    Suggest,
+}
+
+impl TryFrom<&str> for Command {
+    type Error = ();
+
+    fn try_from(input: &str) -> Result<Self, Self::Error> {
+        let input = input.trim().trim_start_matches(':');
+        let (name, argument) = input
+            .split_once(char::is_whitespace)
+            .map_or((input, ""), |(name, argument)| (name, argument.trim()));
+        match (name, argument) {
+            ("w", "") => Ok(Self::Write),
+            ("q", "") => Ok(Self::Quit),
+            ("q!", "") => Ok(Self::ForceQuit),
+            ("wq" | "x", "") => Ok(Self::WriteQuit),
+            ("e!", "") => Ok(Self::Reload),
+            ("e", file_name) if !file_name.is_empty() => Ok(Self::Edit(file_name.to_string())),
+            ("recent", "") => Ok(Self::Recent),
+            ("set", option) if !option.is_empty() => Ok(Self::Set(option.to_string())),
+            ("stats", "") => Ok(Self::Stats),
+            ("earlier", "") => Ok(Self::Earlier),
+            ("later", "") => Ok(Self::Later),
+            ("undolist", "") => Ok(Self::UndoList),
+            ("join" | "j", "") => Ok(Self::Join),
+            ("mark" | "k", name) => single_char(name).map(Self::Mark),
+            ("jump", name) => single_char(name).map(Self::JumpToMark),
+            ("suggest", "") => Ok(Self::Suggest),
+            ("goto", line_number) => line_number.parse().map(Self::GoTo).map_err(|_| ()),
+            (line_number, "") => line_number.parse().map(Self::GoTo).map_err(|_| ()),
+            _ => Err(()),
+        }
+    }
+}
+
+fn single_char(argument: &str) -> Result<char, ()> {
+    let mut chars = argument.chars();
+    match (chars.next(), chars.next()) {
+        (Some(name), None) => Ok(name),
+        _ => Err(()),
+    }
+}
+
+/// An option changed with `:set`. Boolean options are switched off with a
+/// `no` prefix, as in `nowrap`.
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum Setting {
+    TabWidth(usize),
+    ExpandTab(bool),
+    Number(bool),
+    ReadOnly(bool),
+    Wrap(bool),
+    Spell(bool),
+}
+
+impl TryFrom<&str> for Setting {
+    type Error = String;
+
+    fn try_from(option: &str) -> Result<Self, Self::Error> {
+        if let Some((name, value)) = option.split_once('=') {
+            return match name.trim() {
+                "tabwidth" => value
+                    .trim()
+                    .parse()
+                    .ok()
+                    .filter(|&tab_width| tab_width > 0)
+                    .map(Self::TabWidth)
+                    .ok_or_else(|| format!("Invalid tab width: {}", value.trim())),
+                name => Err(format!("Unknown option: {name}")),
+            };
+        }
+        let (enabled, name) = option
+            .strip_prefix("no")
+            .map_or((true, option), |name| (false, name));
+        match name {
+            "expandtab" => Ok(Self::ExpandTab(enabled)),
+            "number" => Ok(Self::Number(enabled)),
+            "readonly" => Ok(Self::ReadOnly(enabled)),
+            "wrap" => Ok(Self::Wrap(enabled)),
+            "spell" => Ok(Self::Spell(enabled)),
+            "tabwidth" if enabled => Err(String::from("tabwidth needs a value")),
+            _ => Err(format!("Unknown option: {option}")),
+        }
+    }
+}
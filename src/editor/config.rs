@@ -0,0 +1,126 @@
+use super::spell::DEFAULT_DICTIONARY;
+use super::terminal::CaretStyle;
+use super::view::Options;
+use serde::Deserialize;
+use std::env;
+use std::fs::read_to_string;
+use std::io::{Error, ErrorKind};
+use std::path::PathBuf;
+
+const DEFAULT_QUIT_TIMES: u8 = 3;
+
+/// Where the editor keeps `file_name` between sessions: `rustamundo/` in
+/// `$XDG_STATE_HOME` or `~/.local/state`.
+pub fn state_path(file_name: &str) -> Option<PathBuf> {
+    env::var_os("XDG_STATE_HOME")
+        .filter(|dir| !dir.is_empty())
+        .map(PathBuf::from)
+        .or_else(|| {
+            env::var_os("HOME").map(|home| PathBuf::from(home).join(".local").join("state"))
+        })
+        .map(|dir| dir.join("rustamundo").join(file_name))
+}
+
+/// Settings read from `rustamundo/config.toml` in `$XDG_CONFIG_HOME` or
+/// `~/.config`. Keys left out keep their built-in defaults.
+#[derive(Deserialize)]
+#[serde(default, deny_unknown_fields)]
+#[allow(clippy::struct_excessive_bools)]
+pub struct Config {
+    pub tab_width: usize,
+    pub expand_tab: bool,
+    pub smart_backspace: bool,
+    /// Whether typing a bracket or quote also types its closing partner.
+    pub auto_pairs: bool,
+    pub show_line_numbers: bool,
+    pub scroll_lines: usize,
+    /// How many lines of context to keep above and below the caret.
+    pub scroll_off: usize,
+    /// Whether to show where the screen is in the document along the right
+    /// edge.
+    pub scrollbar: bool,
+    pub backup: bool,
+    pub trim_trailing_whitespace: bool,
+    pub ensure_final_newline: bool,
+    pub insert_final_newline: bool,
+    /// The 1-based column to draw a ruler at, if any.
+    pub color_column: Option<usize>,
+    /// Whether a search that reaches the end of the document carries on
+    /// from the other end.
+    pub search_wrap: bool,
+    /// Whether to underline words that aren't in the dictionary.
+    pub spell_check: bool,
+    /// The word list to check spelling against, one word per line.
+    pub dictionary: PathBuf,
+    pub quit_times: u8,
+    pub caret_style: CaretStyle,
+    pub theme: String,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        let options = Options::default();
+        Self {
+            tab_width: options.tab_width,
+            expand_tab: options.expand_tab,
+            smart_backspace: options.smart_backspace,
+            auto_pairs: options.auto_pairs,
+            show_line_numbers: false,
+            scroll_lines: options.scroll_lines,
+            scroll_off: options.scroll_off,
+            scrollbar: options.scrollbar,
+            backup: options.backup,
+            trim_trailing_whitespace: options.trim_trailing_whitespace,
+            ensure_final_newline: options.ensure_final_newline,
+            insert_final_newline: options.insert_final_newline,
+            color_column: None,
+            search_wrap: true,
+            spell_check: false,
+            dictionary: PathBuf::from(DEFAULT_DICTIONARY),
+            quit_times: DEFAULT_QUIT_TIMES,
+            caret_style: CaretStyle::default(),
+            theme: String::from("dark"),
+        }
+    }
+}
+
+impl Config {
+    /// Reads the config file, or returns the defaults if there is none.
+    pub fn load() -> Result<Self, Error> {
+        let Some(path) = Self::path() else {
+            return Ok(Self::default());
+        };
+        match read_to_string(path) {
+            Ok(contents) => toml::from_str(&contents)
+                .map_err(|err| Error::new(ErrorKind::InvalidData, err.message().to_string())),
+            Err(err) if err.kind() == ErrorKind::NotFound => Ok(Self::default()),
+            Err(err) => Err(err),
+        }
+    }
+
+    fn path() -> Option<PathBuf> {
+        env::var_os("XDG_CONFIG_HOME")
+            .filter(|dir| !dir.is_empty())
+            .map(PathBuf::from)
+            .or_else(|| env::var_os("HOME").map(|home| PathBuf::from(home).join(".config")))
+            .map(|dir| dir.join("rustamundo").join("config.toml"))
+    }
+
+    pub fn options(&self) -> Options {
+        Options {
+            tab_width: self.tab_width.max(1),
+            expand_tab: self.expand_tab,
+            smart_backspace: self.smart_backspace,
+            auto_pairs: self.auto_pairs,
+            scroll_lines: self.scroll_lines,
+            scroll_off: self.scroll_off,
+            scrollbar: self.scrollbar,
+            backup: self.backup,
+            trim_trailing_whitespace: self.trim_trailing_whitespace,
+            ensure_final_newline: self.ensure_final_newline,
+            insert_final_newline: self.insert_final_newline,
+            color_column: self.color_column.and_then(|column| column.checked_sub(1)),
+            ..Options::default()
+        }
+    }
+}
@@ -0,0 +1,48 @@
+use core::fmt::{self, Display};
+use std::path::Path;
+
+#[derive(Default, Clone, Copy, PartialEq, Eq)]
+pub enum FileType {
+    Rust,
+    C,
+    Markdown,
+    #[default]
+    PlainText,
+}
+
+impl FileType {
+    pub fn from_file_name(file_name: &str) -> Self {
+        let Some(extension) = Path::new(file_name)
+            .extension()
+            .and_then(|extension| extension.to_str())
+        else {
+            return Self::PlainText;
+        };
+        match extension.to_ascii_lowercase().as_str() {
+            "rs" => Self::Rust,
+            "c" | "h" => Self::C,
+            "md" | "markdown" => Self::Markdown,
+            _ => Self::PlainText,
+        }
+    }
+
+    /// What starts a line comment, for languages that have them.
+    pub const fn line_comment(self) -> Option<&'static str> {
+        match self {
+            Self::Rust | Self::C => Some("//"),
+            Self::Markdown | Self::PlainText => None,
+        }
+    }
+}
+
+impl Display for FileType {
+    fn fmt(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        let name = match self {
+            Self::Rust => "Rust",
+            Self::C => "C",
+            Self::Markdown => "Markdown",
+            Self::PlainText => "Text",
+        };
+        write!(formatter, "{name}")
+    }
+}
@@ -0,0 +1,82 @@
+use super::terminal::{Position, Size, Terminal};
+use core::cmp::min;
+use std::time::{Duration, Instant};
+use unicode_width::UnicodeWidthStr;
+
+const DEFAULT_DURATION: Duration = Duration::new(5, 0);
+
+struct Message {
+    text: String,
+    time: Instant,
+}
+
+impl Default for Message {
+    fn default() -> Self {
+        Self {
+            text: String::new(),
+            time: Instant::now(),
+        }
+    }
+}
+
+impl Message {
+    fn is_expired(&self) -> bool {
+        Instant::now().duration_since(self.time) > DEFAULT_DURATION
+    }
+}
+
+#[derive(Default)]
+pub struct MessageBar {
+    current_message: Message,
+    needs_redraw: bool,
+    cleared_after_expiry: bool,
+    width: usize,
+    position_y: usize,
+}
+
+impl MessageBar {
+    pub fn resize(&mut self, to: Size) {
+        self.width = to.width;
+        self.position_y = to.height.saturating_sub(1);
+        self.needs_redraw = true;
+    }
+
+    pub fn update_message(&mut self, new_message: &str) {
+        self.current_message = Message {
+            text: new_message.to_string(),
+            time: Instant::now(),
+        };
+        self.cleared_after_expiry = false;
+        self.needs_redraw = true;
+    }
+
+    pub fn caret_position(&self) -> Position {
+        Position {
+            col: min(
+                self.current_message.text.width(),
+                self.width.saturating_sub(1),
+            ),
+            row: self.position_y,
+        }
+    }
+
+    pub fn needs_redraw(&self) -> bool {
+        self.needs_redraw || (self.current_message.is_expired() && !self.cleared_after_expiry)
+    }
+
+    pub fn render(&mut self) {
+        if !self.needs_redraw() {
+            return;
+        }
+        let expired = self.current_message.is_expired();
+        let line: String = if expired {
+            String::new()
+        } else {
+            self.current_message.text.chars().take(self.width).collect()
+        };
+        let result = Terminal::print_row(self.position_y, &line);
+        debug_assert!(result.is_ok(), "Failed to render message bar");
+        self.cleared_after_expiry = expired;
+        self.needs_redraw = false;
+    }
+}
@@ -0,0 +1,33 @@
+use core::fmt::{self, Display};
+
+/// The two modes of the optional vim-like interface. In Normal mode plain
+/// keys are commands; in Insert mode they type text as usual.
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum Mode {
+    Normal,
+    Insert,
+}
+
+impl Display for Mode {
+    fn fmt(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        let name = match self {
+            Self::Normal => "NORMAL",
+            Self::Insert => "INSERT",
+        };
+        write!(formatter, "{name}")
+    }
+}
+
+/// A Normal mode edit that `.` can repeat.
+#[derive(Clone, PartialEq, Eq)]
+pub enum RepeatableEdit {
+    /// The text typed in one visit to Insert mode, entered after the caret
+    /// with `a` or at it with `i`.
+    Insert {
+        after_caret: bool,
+        text: String,
+    },
+    DeleteChars(usize),
+    CutLines(usize),
+    JoinLines,
+}
@@ -0,0 +1,40 @@
+use super::terminal::Size;
+use super::view::Viewport;
+
+/// A horizontal slice of the screen showing one of the open buffers. The
+/// focused pane's viewport lives in its view; every other pane keeps its own
+/// here and swaps it in to render, so two panes on the same buffer keep
+/// separate carets and scroll offsets.
+#[derive(Default)]
+pub struct Pane {
+    pub view: usize,
+    pub viewport: Viewport,
+}
+
+/// Splits `size` among `count` stacked panes with a divider row below each
+/// pane but the last, returning each pane's first row and size. The last
+/// pane takes any rows left over.
+pub fn layout(count: usize, size: Size) -> Vec<(usize, Size)> {
+    let dividers = count.saturating_sub(1);
+    let usable = size.height.saturating_sub(dividers);
+    let pane_height = usable.checked_div(count).unwrap_or(0);
+    let mut origin_row: usize = 0;
+    (0..count)
+        .map(|pane_idx| {
+            let height = if pane_idx == dividers {
+                size.height.saturating_sub(origin_row)
+            } else {
+                pane_height
+            };
+            let placement = (
+                origin_row,
+                Size {
+                    height,
+                    width: size.width,
+                },
+            );
+            origin_row = origin_row.saturating_add(height).saturating_add(1);
+            placement
+        })
+        .collect()
+}
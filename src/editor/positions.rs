@@ -0,0 +1,63 @@
+use super::Location;
+use super::config::state_path;
+use std::collections::BTreeMap;
+use std::fs::{canonicalize, create_dir_all, read_to_string, write};
+use std::io::Error;
+use std::path::PathBuf;
+
+/// The caret location last seen in each file, kept in the `positions` state
+/// file as one `row<TAB>col<TAB>path` line per file.
+#[derive(Default)]
+pub struct Positions {
+    entries: BTreeMap<PathBuf, Location>,
+}
+
+impl Positions {
+    /// Reads the saved positions. A missing or unreadable file counts as
+    /// empty and lines that do not parse are dropped.
+    pub fn load() -> Self {
+        let entries = state_path("positions")
+            .and_then(|path| read_to_string(path).ok())
+            .map(|contents| contents.lines().filter_map(Self::parse_line).collect())
+            .unwrap_or_default();
+        Self { entries }
+    }
+
+    fn parse_line(line: &str) -> Option<(PathBuf, Location)> {
+        let mut fields = line.splitn(3, '\t');
+        let y = fields.next()?.parse().ok()?;
+        let x = fields.next()?.parse().ok()?;
+        let path = fields.next().filter(|path| !path.is_empty())?;
+        Some((PathBuf::from(path), Location { x, y }))
+    }
+
+    pub fn get(&self, file_name: &str) -> Option<Location> {
+        self.entries.get(&canonicalize(file_name).ok()?).copied()
+    }
+
+    pub fn set(&mut self, file_name: &str, at: Location) {
+        if let Ok(path) = canonicalize(file_name) {
+            self.entries.insert(path, at);
+        }
+    }
+
+    /// Writes the positions back, leaving out files that no longer exist.
+    pub fn save(&mut self) -> Result<(), Error> {
+        let Some(path) = state_path("positions") else {
+            return Ok(());
+        };
+        self.entries.retain(|file, _| file.exists());
+        let contents: String = self
+            .entries
+            .iter()
+            .filter_map(|(file, at)| {
+                let file = file.to_str().filter(|file| !file.contains('\n'))?;
+                Some(format!("{}\t{}\t{file}\n", at.y, at.x))
+            })
+            .collect();
+        if let Some(dir) = path.parent() {
+            create_dir_all(dir)?;
+        }
+        write(path, contents)
+    }
+}
@@ -0,0 +1,70 @@
+use super::config::state_path;
+use std::fs::{canonicalize, create_dir_all, read_to_string, write};
+use std::io::Error;
+use std::path::{Path, PathBuf};
+
+const DEFAULT_CAPACITY: usize = 20;
+
+/// The files opened most recently, newest first, kept in the `recent` state
+/// file as one path per line.
+pub struct RecentFiles {
+    paths: Vec<PathBuf>,
+    capacity: usize,
+}
+
+impl Default for RecentFiles {
+    fn default() -> Self {
+        Self {
+            paths: Vec::new(),
+            capacity: DEFAULT_CAPACITY,
+        }
+    }
+}
+
+impl RecentFiles {
+    /// Reads the list, which is empty if the file is missing or unreadable.
+    pub fn load() -> Self {
+        let mut recent_files = Self::default();
+        if let Some(contents) = state_path("recent").and_then(|path| read_to_string(path).ok()) {
+            for line in contents.lines().filter(|line| !line.is_empty()) {
+                let path = PathBuf::from(line);
+                if !recent_files.paths.contains(&path) {
+                    recent_files.paths.push(path);
+                }
+            }
+            recent_files.paths.truncate(recent_files.capacity);
+        }
+        recent_files
+    }
+
+    /// Moves `path` to the front, dropping the oldest entry if the list is
+    /// full. Paths that do not name an existing file are ignored.
+    pub fn push(&mut self, path: impl AsRef<Path>) {
+        let Ok(path) = canonicalize(path) else {
+            return;
+        };
+        self.paths.retain(|recent| *recent != path);
+        self.paths.insert(0, path);
+        self.paths.truncate(self.capacity);
+    }
+
+    pub fn list(&self) -> Vec<PathBuf> {
+        self.paths.clone()
+    }
+
+    pub fn save(&self) -> Result<(), Error> {
+        let Some(path) = state_path("recent") else {
+            return Ok(());
+        };
+        let contents: String = self
+            .paths
+            .iter()
+            .filter_map(|recent| recent.to_str().filter(|recent| !recent.contains('\n')))
+            .flat_map(|recent| [recent, "\n"])
+            .collect();
+        if let Some(dir) = path.parent() {
+            create_dir_all(dir)?;
+        }
+        write(path, contents)
+    }
+}
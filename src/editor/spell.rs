@@ -0,0 +1,92 @@
+use std::collections::HashSet;
+use std::fs::read_to_string;
+use std::io::Error;
+use std::path::Path;
+
+pub const DEFAULT_DICTIONARY: &str = "/usr/share/dict/words";
+const MAX_SUGGESTIONS: usize = 10;
+
+/// A word list, one word per line, looked up without regard to case.
+pub struct Dictionary {
+    words: HashSet<String>,
+}
+
+impl Dictionary {
+    pub fn load(path: &Path) -> Result<Self, Error> {
+        let words = read_to_string(path)?
+            .lines()
+            .map(str::trim)
+            .filter(|word| !word.is_empty())
+            .map(str::to_lowercase)
+            .collect();
+        Ok(Self { words })
+    }
+
+    /// Whether `word` is not in the dictionary. Single letters and words
+    /// with digits or other non-letters in them are never misspelled.
+    pub fn is_misspelled(&self, word: &str) -> bool {
+        let word = word.trim_end_matches("'s").trim_matches('\'');
+        if word.chars().nth(1).is_none()
+            || !word
+                .chars()
+                .all(|character| character.is_alphabetic() || character == '\'')
+        {
+            return false;
+        }
+        !self.words.contains(&word.to_lowercase())
+    }
+
+    /// Dictionary words one letter away from `word`: with a letter left
+    /// out, swapped with the next, changed or added. They keep a leading
+    /// capital if `word` has one.
+    pub fn suggestions(&self, word: &str) -> Vec<String> {
+        let lower = word.to_lowercase();
+        let letters: Vec<char> = lower.chars().collect();
+        let mut candidates = Vec::new();
+        for idx in 0..=letters.len() {
+            let (before, after) = letters.split_at(idx);
+            let before: String = before.iter().collect();
+            if let Some((&current, rest)) = after.split_first() {
+                if let Some((&next, past_next)) = rest.split_first() {
+                    let past_next: String = past_next.iter().collect();
+                    candidates.push(format!("{before}{next}{current}{past_next}"));
+                }
+                let rest: String = rest.iter().collect();
+                candidates.push(format!("{before}{rest}"));
+                for letter in 'a'..='z' {
+                    candidates.push(format!("{before}{letter}{rest}"));
+                }
+            }
+            let after: String = after.iter().collect();
+            for letter in 'a'..='z' {
+                candidates.push(format!("{before}{letter}{after}"));
+            }
+        }
+        let capitalized = word.chars().next().is_some_and(char::is_uppercase);
+        let mut suggestions: Vec<String> = Vec::new();
+        for candidate in candidates {
+            if candidate == lower || !self.words.contains(&candidate) {
+                continue;
+            }
+            let candidate = if capitalized {
+                capitalize(&candidate)
+            } else {
+                candidate
+            };
+            if !suggestions.contains(&candidate) {
+                suggestions.push(candidate);
+            }
+            if suggestions.len() == MAX_SUGGESTIONS {
+                break;
+            }
+        }
+        suggestions
+    }
+}
+
+fn capitalize(word: &str) -> String {
+    let mut chars = word.chars();
+    chars.next().map_or_else(String::new, |first| {
+        first.to_uppercase().chain(chars).collect()
+    })
+}
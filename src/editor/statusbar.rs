@@ -0,0 +1,151 @@
+use super::Location;
+use super::filetype::FileType;
+use super::mode::Mode;
+use super::terminal::{Size, Terminal};
+
+#[derive(Default, Clone, PartialEq, Eq)]
+pub struct DocumentStatus {
+    pub file_name: Option<String>,
+    pub file_type: FileType,
+    pub line_count: usize,
+    pub is_modified: bool,
+    pub is_read_only: bool,
+    pub caret: Location,
+    pub buffer_index: usize,
+    pub buffer_count: usize,
+    pub mode: Option<Mode>,
+}
+
+impl DocumentStatus {
+    fn file_name_string(&self) -> String {
+        self.file_name
+            .clone()
+            .unwrap_or_else(|| String::from("[No Name]"))
+    }
+
+    fn buffer_indicator_string(&self) -> String {
+        if self.buffer_count > 1 {
+            format!(
+                "[{}/{}] ",
+                self.buffer_index.saturating_add(1),
+                self.buffer_count
+            )
+        } else {
+            String::new()
+        }
+    }
+
+    fn line_count_string(&self) -> String {
+        format!("{} lines", self.line_count)
+    }
+
+    fn modified_indicator_string(&self) -> String {
+        if self.is_modified {
+            String::from(" [modified]")
+        } else {
+            String::new()
+        }
+    }
+
+    fn read_only_indicator_string(&self) -> String {
+        if self.is_read_only {
+            String::from(" [RO]")
+        } else {
+            String::new()
+        }
+    }
+
+    fn mode_indicator_string(&self) -> String {
+        self.mode
+            .map_or_else(String::new, |mode| format!("{mode} | "))
+    }
+
+    fn position_indicator_string(&self) -> String {
+        format!(
+            "{}:{}",
+            self.caret.y.saturating_add(1),
+            self.caret.x.saturating_add(1)
+        )
+    }
+
+    /// How far down the document the caret is, like vim's ruler: `Top` on
+    /// the first line, `Bot` on the last and a percentage in between.
+    fn percentage_indicator_string(&self) -> String {
+        let last_line = self.line_count.saturating_sub(1);
+        if self.caret.y == 0 {
+            String::from("Top")
+        } else if self.caret.y >= last_line {
+            String::from("Bot")
+        } else {
+            let percentage = self
+                .caret
+                .y
+                .saturating_add(1)
+                .saturating_mul(100)
+                .checked_div(self.line_count)
+                .unwrap_or(0);
+            format!("{percentage}%")
+        }
+    }
+}
+
+#[derive(Default)]
+pub struct StatusBar {
+    current_status: DocumentStatus,
+    needs_redraw: bool,
+    width: usize,
+    position_y: usize,
+}
+
+impl StatusBar {
+    pub fn resize(&mut self, to: Size) {
+        self.width = to.width;
+        self.position_y = to.height.saturating_sub(1);
+        self.needs_redraw = true;
+    }
+
+    pub fn update_status(&mut self, new_status: DocumentStatus) {
+        if new_status != self.current_status {
+            self.current_status = new_status;
+            self.needs_redraw = true;
+        }
+    }
+
+    pub const fn needs_redraw(&self) -> bool {
+        self.needs_redraw
+    }
+
+    pub fn render(&mut self) {
+        if !self.needs_redraw || self.width == 0 {
+            return;
+        }
+        let status = &self.current_status;
+        let beginning = format!(
+            "{}{} - {}{}{}",
+            status.buffer_indicator_string(),
+            status.file_name_string(),
+            status.line_count_string(),
+            status.modified_indicator_string(),
+            status.read_only_indicator_string()
+        );
+        let position = format!(
+            "{}{} | {} {}",
+            status.mode_indicator_string(),
+            status.file_type,
+            status.position_indicator_string(),
+            status.percentage_indicator_string()
+        );
+        let remainder_len = self.width.saturating_sub(beginning.chars().count());
+        let mut line: String = format!("{beginning}{position:>remainder_len$}")
+            .chars()
+            .take(self.width)
+            .collect();
+        let padding = self.width.saturating_sub(line.chars().count());
+        line.push_str(&" ".repeat(padding));
+
+        let result =
+            Terminal::print_colored_row(self.position_y, &line, Terminal::theme().status_bar);
+        debug_assert!(result.is_ok(), "Failed to render status bar");
+        self.needs_redraw = false;
+    }
+}
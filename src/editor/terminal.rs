@@ -1,66 +1,156 @@
+use super::annotatedstring::AnnotatedString;
+use super::theme::{Colors, Theme};
 use core::fmt::Display;
-use crossterm::cursor::{Hide, MoveTo, Show};
-use crossterm::style::Print;
-use crossterm::terminal::{
-    Clear, ClearType, EnterAlternateScreen, LeaveAlternateScreen, disable_raw_mode,
-    enable_raw_mode, size,
+use crossterm::cursor::{Hide, MoveTo, SetCursorStyle, Show};
+use crossterm::event::{
+    DisableBracketedPaste, DisableMouseCapture, EnableBracketedPaste, EnableMouseCapture, Event,
 };
+use crossterm::style::{Print, ResetColor, SetAttribute, SetBackgroundColor, SetForegroundColor};
+use crossterm::terminal::{Clear, ClearType, EnterAlternateScreen, LeaveAlternateScreen};
 use crossterm::{Command, queue};
-use std::io::{Error, Write, stdout};
+use serde::Deserialize;
+use std::cell::{Cell, RefCell};
+use std::io::Error;
+use unicode_segmentation::UnicodeSegmentation;
+use unicode_width::UnicodeWidthStr;
 
-#[derive(Default, Clone, Copy)]
+mod attribute;
+mod color;
+mod target;
+pub use attribute::{Attribute, Attributes};
+pub use color::Color;
+#[cfg(test)]
+pub use target::Recorder;
+use target::{RenderTarget, Stdout};
+
+#[derive(Default, Clone, Copy, PartialEq, Eq)]
 pub struct Size {
     pub height: usize,
     pub width: usize,
 }
 
-#[derive(Clone, Copy, Default)]
+#[derive(Clone, Copy, Default, PartialEq, Eq)]
 pub struct Position {
     pub col: usize,
     pub row: usize,
 }
 
+#[derive(Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum CaretStyle {
+    #[default]
+    Block,
+    Bar,
+    Underline,
+}
+
+impl TryFrom<&str> for CaretStyle {
+    type Error = ();
+
+    fn try_from(name: &str) -> Result<Self, Self::Error> {
+        match name {
+            "block" => Ok(Self::Block),
+            "bar" => Ok(Self::Bar),
+            "underline" => Ok(Self::Underline),
+            _ => Err(()),
+        }
+    }
+}
+
+/// A grapheme on screen and how it is drawn.
+#[derive(Clone, PartialEq, Eq)]
+struct ScreenCell {
+    grapheme: String,
+    colors: Colors,
+    attributes: Attributes,
+}
+
+thread_local! {
+    /// Commands queued for the current frame, written to the target in one go
+    /// by `Terminal::execute`.
+    static FRAME: RefCell<Vec<u8>> = const { RefCell::new(Vec::new()) };
+    /// Whether the current frame changes anything but where the caret is.
+    static FRAME_CHANGES: Cell<bool> = const { Cell::new(false) };
+    /// Where the caret was put last, and where the last frame left it.
+    static CARET: Cell<Option<Position>> = const { Cell::new(None) };
+    static SHOWN_CARET: Cell<Option<Position>> = const { Cell::new(None) };
+    /// What each row of the screen shows, so that printing a row only sends
+    /// the part that changed.
+    static SCREEN: RefCell<Vec<Vec<ScreenCell>>> = const { RefCell::new(Vec::new()) };
+    static TARGET: RefCell<Box<dyn RenderTarget>> = RefCell::new(Box::new(Stdout));
+    static THEME: Cell<Theme> = Cell::new(Theme::default());
+}
+
 pub struct Terminal;
 
 impl Terminal {
+    /// Sends all output to `target` and reads events from it instead of
+    /// the terminal.
+    #[cfg(test)]
+    pub fn set_target(target: Box<dyn RenderTarget>) {
+        TARGET.set(target);
+    }
+
+    /// Waits for the next key press, resize, paste or mouse event.
+    pub fn read_event() -> Result<Event, Error> {
+        TARGET.with_borrow_mut(|target| target.read_event())
+    }
+
     pub fn initialize() -> Result<(), Error> {
-        enable_raw_mode()?;
+        TARGET.with_borrow_mut(|target| target.set_raw_mode(true))?;
         Self::enter_alternate_screen()?;
+        Self::queue_command(EnableMouseCapture)?;
+        Self::queue_command(EnableBracketedPaste)?;
         Self::clear_screen()?;
         Self::execute()?;
         Ok(())
     }
 
     pub fn terminate() -> Result<(), Error> {
+        Self::queue_command(SetCursorStyle::DefaultUserShape)?;
+        Self::queue_command(DisableBracketedPaste)?;
+        Self::queue_command(DisableMouseCapture)?;
         Self::leave_alternate_screen()?;
         Self::show_caret()?;
         Self::execute()?;
-        disable_raw_mode()?;
+        TARGET.with_borrow_mut(|target| target.set_raw_mode(false))?;
         Ok(())
     }
 
     pub fn clear_screen() -> Result<(), Error> {
         Self::queue_command(Clear(ClearType::All))?;
+        SCREEN.with_borrow_mut(Vec::clear);
         Ok(())
     }
 
-    pub fn clear_line() -> Result<(), Error> {
-        Self::queue_command(Clear(ClearType::CurrentLine))?;
+    /// Puts the caret at `position` once the frame is done.
+    pub fn move_caret_to(position: Position) -> Result<(), Error> {
+        CARET.set(Some(position));
+        FRAME.with_borrow_mut(|frame| queue!(frame, Self::move_to(position)))?;
         Ok(())
     }
 
-    pub fn move_caret_to(position: Position) -> Result<(), Error> {
-        Self::queue_command(MoveTo(position.col as u16, position.row as u16))?;
-        Ok(())
+    fn move_to(position: Position) -> MoveTo {
+        #[allow(clippy::as_conversions, clippy::cast_possible_truncation)]
+        MoveTo(position.col as u16, position.row as u16)
     }
 
     pub fn hide_caret() -> Result<(), Error> {
-        Self::queue_command(Hide)?;
+        FRAME.with_borrow_mut(|frame| queue!(frame, Hide))?;
+        Ok(())
+    }
+
+    pub fn set_caret_style(style: CaretStyle) -> Result<(), Error> {
+        Self::queue_command(match style {
+            CaretStyle::Block => SetCursorStyle::SteadyBlock,
+            CaretStyle::Bar => SetCursorStyle::SteadyBar,
+            CaretStyle::Underline => SetCursorStyle::SteadyUnderScore,
+        })?;
         Ok(())
     }
 
     pub fn show_caret() -> Result<(), Error> {
-        Self::queue_command(Show)?;
+        FRAME.with_borrow_mut(|frame| queue!(frame, Show))?;
         Ok(())
     }
 
@@ -69,20 +159,70 @@ impl Terminal {
         Ok(())
     }
 
+    pub fn theme() -> Theme {
+        THEME.with(Cell::get)
+    }
+
+    pub fn set_theme(theme: Theme) {
+        THEME.with(|current| current.set(theme));
+    }
+
+    pub fn set_colors((foreground, background): Colors) -> Result<(), Error> {
+        if let Some(foreground) = foreground {
+            Self::set_foreground_color(foreground)?;
+        }
+        if let Some(background) = background {
+            Self::set_background_color(background)?;
+        }
+        Ok(())
+    }
+
+    pub fn set_foreground_color(color: Color) -> Result<(), Error> {
+        Self::queue_command(SetForegroundColor(color.into()))?;
+        Ok(())
+    }
+
+    pub fn set_background_color(color: Color) -> Result<(), Error> {
+        Self::queue_command(SetBackgroundColor(color.into()))?;
+        Ok(())
+    }
+
+    pub fn reset_color() -> Result<(), Error> {
+        Self::queue_command(ResetColor)?;
+        Ok(())
+    }
+
+    pub fn set_attribute(attribute: Attribute) -> Result<(), Error> {
+        Self::queue_command(SetAttribute(attribute.into()))?;
+        Ok(())
+    }
+
+    /// Turns off all attributes, and with them the colors.
+    pub fn reset_attributes() -> Result<(), Error> {
+        Self::set_attribute(Attribute::Reset)
+    }
+
     pub fn size() -> Result<Size, Error> {
-        let (width_u16, height_u16) = size()?;
-        let height = height_u16 as usize;
-        let width = width_u16 as usize;
-        Ok(Size { height, width })
+        TARGET.with_borrow(|target| target.size())
     }
 
+    /// Writes out the frame, unless it would leave the screen and the caret
+    /// as they are.
     pub fn execute() -> Result<(), Error> {
-        stdout().flush()?;
-        Ok(())
+        let changes = FRAME_CHANGES.replace(false);
+        let caret_moved = SHOWN_CARET.replace(CARET.get()) != CARET.get();
+        FRAME.with_borrow_mut(|frame| {
+            if changes || caret_moved {
+                TARGET.with_borrow_mut(|target| target.write_frame(frame))?;
+            }
+            frame.clear();
+            Ok(())
+        })
     }
 
     fn queue_command<T: Command>(command: T) -> Result<(), Error> {
-        queue!(stdout(), command)?;
+        FRAME.with_borrow_mut(|frame| queue!(frame, command))?;
+        FRAME_CHANGES.set(true);
         Ok(())
     }
 
@@ -97,9 +237,197 @@ impl Terminal {
     }
 
     pub fn print_row(row: usize, line_text: &str) -> Result<(), Error> {
-        Self::move_caret_to(Position { col: 0, row })?;
-        Self::clear_line()?;
-        Self::print(line_text)?;
-        Ok(())
+        Self::print_colored_row(row, line_text, (None, None))
+    }
+
+    pub fn print_colored_row(row: usize, line_text: &str, colors: Colors) -> Result<(), Error> {
+        Self::print_cells(
+            row,
+            Self::cells(line_text, colors, Attributes::NONE).collect(),
+        )
+    }
+
+    pub fn print_annotated_row(
+        row: usize,
+        annotated_string: &AnnotatedString,
+    ) -> Result<(), Error> {
+        let theme = Self::theme();
+        let cells = annotated_string
+            .parts()
+            .flat_map(|part| {
+                let colors = part.kind.map_or((None, None), |kind| theme.colors(kind));
+                let attributes = part.kind.map_or(Attributes::NONE, Theme::attributes);
+                let attributes = if part.underlined {
+                    attributes.with(Attributes::UNDERLINED)
+                } else {
+                    attributes
+                };
+                Self::cells(&part.string, colors, attributes)
+            })
+            .collect();
+        Self::print_cells(row, cells)
+    }
+
+    fn cells(
+        text: &str,
+        colors: Colors,
+        attributes: Attributes,
+    ) -> impl Iterator<Item = ScreenCell> {
+        text.graphemes(true).map(move |grapheme| ScreenCell {
+            grapheme: grapheme.to_string(),
+            colors,
+            attributes,
+        })
+    }
+
+    /// Shows `cells` on `row`, sending only the span that differs from what
+    /// the row already shows.
+    fn print_cells(row: usize, cells: Vec<ScreenCell>) -> Result<(), Error> {
+        SCREEN.with_borrow_mut(|screen| {
+            if screen.len() <= row {
+                screen.resize(row.saturating_add(1), Vec::new());
+            }
+            let Some(shown) = screen.get_mut(row) else {
+                return Ok(());
+            };
+            let start = shown
+                .iter()
+                .zip(&cells)
+                .take_while(|(shown, new)| shown == new)
+                .count();
+            if start == shown.len() && start == cells.len() {
+                return Ok(());
+            }
+            let width = |cells: &[ScreenCell]| -> usize {
+                cells.iter().map(|cell| cell.grapheme.width()).sum()
+            };
+            // Text after the change stays in place only if the row keeps its
+            // layout, so only then can the unchanged end be skipped too.
+            let same_layout = shown.len() == cells.len() && width(shown) == width(&cells);
+            let end = if same_layout {
+                let unchanged_end = shown
+                    .iter()
+                    .rev()
+                    .zip(cells.iter().rev())
+                    .take_while(|(shown, new)| shown == new)
+                    .count();
+                cells.len().saturating_sub(unchanged_end)
+            } else {
+                cells.len()
+            };
+            Self::queue_command(Self::move_to(Position {
+                col: width(cells.get(..start).unwrap_or_default()),
+                row,
+            }))?;
+            let changed = cells.get(start..end).unwrap_or_default();
+            for run in changed.chunk_by(|left, right| {
+                left.colors == right.colors && left.attributes == right.attributes
+            }) {
+                let text: String = run.iter().map(|cell| cell.grapheme.as_str()).collect();
+                let (colors, attributes) = run
+                    .first()
+                    .map_or(((None, None), Attributes::NONE), |cell| {
+                        (cell.colors, cell.attributes)
+                    });
+                for attribute in attributes.iter() {
+                    Self::set_attribute(attribute)?;
+                }
+                Self::set_colors(colors)?;
+                Self::print(text)?;
+                // Every run ends with everything reset, so nothing carries on
+                // into the next run, the end of the row or another row.
+                if !attributes.is_empty() {
+                    Self::reset_attributes()?;
+                } else if colors != (None, None) {
+                    Self::reset_color()?;
+                }
+            }
+            if !same_layout && width(shown) > width(&cells) {
+                Self::queue_command(Clear(ClearType::UntilNewLine))?;
+            }
+            *shown = cells;
+            Ok(())
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::annotatedstring::{AnnotatedString, AnnotationType};
+    use super::super::theme::Theme;
+    use super::{Attribute, CaretStyle, Color, Position, Recorder, Size, Terminal};
+    use core::cell::RefCell;
+    use crossterm::event::{Event, KeyCode, KeyEvent, KeyModifiers};
+    use std::rc::Rc;
+
+    const SIZE: Size = Size {
+        height: 4,
+        width: 20,
+    };
+
+    fn record(events: Vec<Event>) -> Rc<RefCell<Vec<u8>>> {
+        let output = Rc::new(RefCell::new(Vec::new()));
+        Terminal::set_target(Box::new(Recorder {
+            output: Rc::clone(&output),
+            events: events.into(),
+            size: SIZE,
+        }));
+        output
+    }
+
+    #[test]
+    fn every_method_runs_against_a_recorder() {
+        let key = Event::Key(KeyEvent::new(KeyCode::Char('q'), KeyModifiers::NONE));
+        let output = record(vec![key.clone()]);
+        Terminal::initialize().unwrap();
+        assert!(Terminal::size().unwrap() == SIZE);
+        Terminal::set_theme(Theme::by_name("light").unwrap());
+        assert_eq!(Terminal::theme().name, "light");
+        Terminal::set_caret_style(CaretStyle::Bar).unwrap();
+        Terminal::hide_caret().unwrap();
+        Terminal::print_row(0, "plain").unwrap();
+        Terminal::print_colored_row(1, "colored", (Some(Color::Red), None)).unwrap();
+        let mut annotated = AnnotatedString::default();
+        annotated.push("fn", Some(AnnotationType::Keyword));
+        annotated.push_underlined(" main", None, true);
+        Terminal::print_annotated_row(2, &annotated).unwrap();
+        Terminal::move_caret_to(Position { col: 3, row: 3 }).unwrap();
+        Terminal::set_colors((Some(Color::Blue), Some(Color::Grey))).unwrap();
+        Terminal::set_foreground_color(Color::Green).unwrap();
+        Terminal::set_background_color(Color::Black).unwrap();
+        Terminal::reset_color().unwrap();
+        Terminal::set_attribute(Attribute::Italic).unwrap();
+        Terminal::reset_attributes().unwrap();
+        Terminal::print("x").unwrap();
+        Terminal::show_caret().unwrap();
+        Terminal::execute().unwrap();
+        assert_eq!(
+            Recorder::screen(&output.borrow(), SIZE),
+            ["plain", "colored", "fn main", "   x"]
+        );
+        assert!(Terminal::read_event().unwrap() == key);
+        assert!(Terminal::read_event().is_err());
+        Terminal::clear_screen().unwrap();
+        Terminal::execute().unwrap();
+        assert_eq!(Recorder::screen(&output.borrow(), SIZE), ["", "", "", ""]);
+        Terminal::enter_alternate_screen().unwrap();
+        Terminal::leave_alternate_screen().unwrap();
+        Terminal::terminate().unwrap();
+    }
+
+    #[test]
+    fn reprinting_a_row_sends_only_what_changed() {
+        let output = record(Vec::new());
+        Terminal::print_row(0, "hello world").unwrap();
+        Terminal::execute().unwrap();
+        let written = output.borrow().len();
+        Terminal::print_row(0, "hello world").unwrap();
+        Terminal::execute().unwrap();
+        assert_eq!(output.borrow().len(), written);
+        Terminal::print_row(0, "hello there").unwrap();
+        Terminal::execute().unwrap();
+        let update = String::from_utf8_lossy(&output.borrow()[written..]).into_owned();
+        assert!(update.ends_with("there"), "{update:?}");
+        assert_eq!(Recorder::screen(&output.borrow(), SIZE)[0], "hello there");
     }
 }
@@ -0,0 +1,58 @@
+use crossterm::style::Attribute as CrosstermAttribute;
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum Attribute {
+    Bold,
+    Italic,
+    Underlined,
+    Reverse,
+    /// Turns off all attributes and colors.
+    Reset,
+}
+
+impl From<Attribute> for CrosstermAttribute {
+    fn from(attribute: Attribute) -> Self {
+        match attribute {
+            Attribute::Bold => Self::Bold,
+            Attribute::Italic => Self::Italic,
+            Attribute::Underlined => Self::Underlined,
+            Attribute::Reverse => Self::Reverse,
+            Attribute::Reset => Self::Reset,
+        }
+    }
+}
+
+/// A set of the attributes text can be drawn with, `Reset` aside.
+#[derive(Clone, Copy, Default, PartialEq, Eq)]
+pub struct Attributes(u8);
+
+impl Attributes {
+    pub const NONE: Self = Self(0);
+    pub const BOLD: Self = Self(1);
+    pub const ITALIC: Self = Self(1 << 1);
+    pub const UNDERLINED: Self = Self(1 << 2);
+    pub const REVERSE: Self = Self(1 << 3);
+
+    const ALL: [(Self, Attribute); 4] = [
+        (Self::BOLD, Attribute::Bold),
+        (Self::ITALIC, Attribute::Italic),
+        (Self::UNDERLINED, Attribute::Underlined),
+        (Self::REVERSE, Attribute::Reverse),
+    ];
+
+    #[must_use]
+    pub const fn with(self, other: Self) -> Self {
+        Self(self.0 | other.0)
+    }
+
+    pub const fn is_empty(self) -> bool {
+        self.0 == 0
+    }
+
+    pub fn iter(self) -> impl Iterator<Item = Attribute> {
+        Self::ALL
+            .into_iter()
+            .filter(move |(flag, _)| self.0 & flag.0 != 0)
+            .map(|(_, attribute)| attribute)
+    }
+}
@@ -0,0 +1,46 @@
+use crossterm::style::Color as CrosstermColor;
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum Color {
+    Black,
+    DarkGrey,
+    Grey,
+    White,
+    Red,
+    DarkRed,
+    Green,
+    DarkGreen,
+    Yellow,
+    DarkYellow,
+    Blue,
+    DarkBlue,
+    Magenta,
+    DarkMagenta,
+    Cyan,
+    DarkCyan,
+    Rgb { r: u8, g: u8, b: u8 },
+}
+
+impl From<Color> for CrosstermColor {
+    fn from(color: Color) -> Self {
+        match color {
+            Color::Black => Self::Black,
+            Color::DarkGrey => Self::DarkGrey,
+            Color::Grey => Self::Grey,
+            Color::White => Self::White,
+            Color::Red => Self::Red,
+            Color::DarkRed => Self::DarkRed,
+            Color::Green => Self::Green,
+            Color::DarkGreen => Self::DarkGreen,
+            Color::Yellow => Self::Yellow,
+            Color::DarkYellow => Self::DarkYellow,
+            Color::Blue => Self::Blue,
+            Color::DarkBlue => Self::DarkBlue,
+            Color::Magenta => Self::Magenta,
+            Color::DarkMagenta => Self::DarkMagenta,
+            Color::Cyan => Self::Cyan,
+            Color::DarkCyan => Self::DarkCyan,
+            Color::Rgb { r, g, b } => Self::Rgb { r, g, b },
+        }
+    }
+}
@@ -0,0 +1,134 @@
+use super::Size;
+use crossterm::event::{Event, read};
+use crossterm::terminal::{disable_raw_mode, enable_raw_mode, size};
+use std::io::{Error, Write, stdout};
+
+/// Where `Terminal` sends finished frames, reads events from and asks for
+/// the screen size.
+pub trait RenderTarget {
+    fn set_raw_mode(&mut self, enabled: bool) -> Result<(), Error>;
+    fn write_frame(&mut self, frame: &[u8]) -> Result<(), Error>;
+    fn read_event(&mut self) -> Result<Event, Error>;
+    fn size(&self) -> Result<Size, Error>;
+}
+
+/// The terminal the editor was started in.
+pub struct Stdout;
+
+impl RenderTarget for Stdout {
+    fn set_raw_mode(&mut self, enabled: bool) -> Result<(), Error> {
+        if enabled {
+            enable_raw_mode()
+        } else {
+            disable_raw_mode()
+        }
+    }
+
+    fn write_frame(&mut self, frame: &[u8]) -> Result<(), Error> {
+        let mut stdout = stdout().lock();
+        stdout.write_all(frame)?;
+        stdout.flush()
+    }
+
+    fn read_event(&mut self) -> Result<Event, Error> {
+        read()
+    }
+
+    fn size(&self) -> Result<Size, Error> {
+        let (width_u16, height_u16) = size()?;
+        #[allow(clippy::as_conversions)]
+        let height = height_u16 as usize;
+        #[allow(clippy::as_conversions)]
+        let width = width_u16 as usize;
+        Ok(Size { height, width })
+    }
+}
+
+/// A screen of fixed size that keeps everything written to it and hands
+/// out queued events, for driving the editor without a terminal.
+#[cfg(test)]
+pub struct Recorder {
+    pub output: std::rc::Rc<core::cell::RefCell<Vec<u8>>>,
+    pub events: std::collections::VecDeque<Event>,
+    pub size: Size,
+}
+
+#[cfg(test)]
+impl Recorder {
+    /// What the recorded output leaves on screen, row by row, with trailing
+    /// blanks trimmed. Only the escape sequences that move the caret or
+    /// clear text matter here; all others are skipped.
+    pub fn screen(output: &[u8], size: Size) -> Vec<String> {
+        use unicode_width::UnicodeWidthChar;
+        let mut grid = vec![vec![' '; size.width]; size.height];
+        let (mut row, mut col) = (0, 0);
+        let text = String::from_utf8_lossy(output);
+        let mut chars = text.chars().peekable();
+        while let Some(character) = chars.next() {
+            if character != '\x1b' {
+                if let Some(cell) = grid.get_mut(row).and_then(|cells| cells.get_mut(col)) {
+                    *cell = character;
+                }
+                col = col.saturating_add(character.width().unwrap_or_default());
+                continue;
+            }
+            if chars.next_if_eq(&'[').is_none() {
+                continue;
+            }
+            let mut params = String::new();
+            let Some(command) = chars.find(|&next| {
+                let is_final = ('@'..='~').contains(&next);
+                if !is_final {
+                    params.push(next);
+                }
+                is_final
+            }) else {
+                break;
+            };
+            let numbers: Vec<usize> = params
+                .split(';')
+                .map(|number| number.parse().unwrap_or(1))
+                .collect();
+            match command {
+                'H' => {
+                    row = numbers.first().copied().unwrap_or(1).saturating_sub(1);
+                    col = numbers.get(1).copied().unwrap_or(1).saturating_sub(1);
+                }
+                'K' => {
+                    if let Some(cells) = grid.get_mut(row) {
+                        cells.iter_mut().skip(col).for_each(|cell| *cell = ' ');
+                    }
+                }
+                'J' if params == "2" => {
+                    grid.iter_mut().flatten().for_each(|cell| *cell = ' ');
+                }
+                _ => {}
+            }
+        }
+        grid.into_iter()
+            .map(|cells| cells.into_iter().collect::<String>().trim_end().to_string())
+            .collect()
+    }
+}
+
+#[cfg(test)]
+impl RenderTarget for Recorder {
+    fn set_raw_mode(&mut self, _enabled: bool) -> Result<(), Error> {
+        Ok(())
+    }
+
+    fn write_frame(&mut self, frame: &[u8]) -> Result<(), Error> {
+        self.output.borrow_mut().extend_from_slice(frame);
+        Ok(())
+    }
+
+    fn read_event(&mut self) -> Result<Event, Error> {
+        self.events
+            .pop_front()
+            .ok_or_else(|| Error::other("no more events"))
+    }
+
+    fn size(&self) -> Result<Size, Error> {
+        Ok(self.size)
+    }
+}
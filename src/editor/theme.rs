@@ -0,0 +1,137 @@
+use super::annotatedstring::AnnotationType;
+use super::terminal::{Attributes, Color};
+
+/// A foreground and background color, where `None` keeps the terminal's
+/// default.
+pub type Colors = (Option<Color>, Option<Color>);
+
+/// The colors everything on screen is drawn with.
+#[derive(Clone, Copy)]
+pub struct Theme {
+    pub name: &'static str,
+    pub status_bar: Colors,
+    pub divider: Colors,
+    matched: Colors,
+    selected_match: Colors,
+    line_number: Colors,
+    selection: Colors,
+    keyword: Colors,
+    string: Colors,
+    character: Colors,
+    number: Colors,
+    comment: Colors,
+    trailing_whitespace: Colors,
+    mixed_indentation: Colors,
+    matching_bracket: Colors,
+    unmatched_bracket: Colors,
+    color_column: Colors,
+    extra_caret: Colors,
+    scrollbar_thumb: Colors,
+    fold: Colors,
+}
+
+const DARK: Theme = Theme {
+    name: "dark",
+    status_bar: (Some(Color::Black), Some(Color::Grey)),
+    divider: (Some(Color::DarkGrey), None),
+    matched: (Some(Color::Black), Some(Color::DarkYellow)),
+    selected_match: (Some(Color::Black), Some(Color::Yellow)),
+    line_number: (Some(Color::DarkGrey), None),
+    selection: (Some(Color::Black), Some(Color::White)),
+    keyword: (Some(Color::Blue), None),
+    string: (Some(Color::Green), None),
+    character: (Some(Color::DarkGreen), None),
+    number: (Some(Color::Magenta), None),
+    comment: (Some(Color::DarkCyan), None),
+    trailing_whitespace: (None, Some(Color::Red)),
+    mixed_indentation: (None, Some(Color::DarkMagenta)),
+    matching_bracket: (None, Some(Color::DarkBlue)),
+    unmatched_bracket: (Some(Color::White), Some(Color::DarkRed)),
+    color_column: (None, Some(Color::DarkGrey)),
+    extra_caret: (Some(Color::Black), Some(Color::Cyan)),
+    scrollbar_thumb: (None, Some(Color::Grey)),
+    fold: (Some(Color::Cyan), Some(Color::DarkGrey)),
+};
+
+const LIGHT: Theme = Theme {
+    name: "light",
+    status_bar: (Some(Color::White), Some(Color::DarkGrey)),
+    divider: (Some(Color::Grey), None),
+    matched: (Some(Color::Black), Some(Color::Yellow)),
+    selected_match: (Some(Color::White), Some(Color::DarkYellow)),
+    line_number: (Some(Color::Grey), None),
+    selection: (Some(Color::White), Some(Color::DarkBlue)),
+    keyword: (Some(Color::DarkBlue), None),
+    string: (Some(Color::DarkGreen), None),
+    character: (Some(Color::DarkCyan), None),
+    number: (Some(Color::DarkMagenta), None),
+    comment: (Some(Color::DarkGrey), None),
+    trailing_whitespace: (None, Some(Color::Red)),
+    mixed_indentation: (None, Some(Color::Magenta)),
+    matching_bracket: (None, Some(Color::Cyan)),
+    unmatched_bracket: (Some(Color::White), Some(Color::Red)),
+    color_column: (None, Some(Color::Grey)),
+    extra_caret: (Some(Color::White), Some(Color::DarkCyan)),
+    scrollbar_thumb: (None, Some(Color::DarkGrey)),
+    fold: (Some(Color::DarkBlue), Some(Color::Grey)),
+};
+
+const THEMES: [Theme; 2] = [DARK, LIGHT];
+
+impl Default for Theme {
+    fn default() -> Self {
+        DARK
+    }
+}
+
+impl Theme {
+    pub fn by_name(name: &str) -> Option<Self> {
+        THEMES
+            .into_iter()
+            .find(|theme| theme.name.eq_ignore_ascii_case(name))
+    }
+
+    /// The built-in theme after this one, wrapping around.
+    pub fn next(self) -> Self {
+        THEMES
+            .iter()
+            .position(|theme| theme.name == self.name)
+            .and_then(|idx| THEMES.get(idx.saturating_add(1)))
+            .copied()
+            .unwrap_or_default()
+    }
+
+    pub const fn colors(&self, kind: AnnotationType) -> Colors {
+        match kind {
+            AnnotationType::Match => self.matched,
+            AnnotationType::SelectedMatch => self.selected_match,
+            AnnotationType::LineNumber => self.line_number,
+            AnnotationType::Selection => self.selection,
+            AnnotationType::Keyword => self.keyword,
+            AnnotationType::String => self.string,
+            AnnotationType::Character => self.character,
+            AnnotationType::Number => self.number,
+            AnnotationType::Comment => self.comment,
+            AnnotationType::TrailingWhitespace => self.trailing_whitespace,
+            AnnotationType::MixedIndentation => self.mixed_indentation,
+            AnnotationType::MatchingBracket => self.matching_bracket,
+            AnnotationType::UnmatchedBracket => self.unmatched_bracket,
+            AnnotationType::ColorColumn => self.color_column,
+            AnnotationType::ExtraCaret => self.extra_caret,
+            AnnotationType::ScrollbarThumb => self.scrollbar_thumb,
+            AnnotationType::Fold => self.fold,
+            // Drawn as an underline rather than in colors of its own.
+            AnnotationType::Misspelled => (None, None),
+        }
+    }
+
+    /// The attributes `kind` is drawn with on top of its colors, the same in
+    /// every theme.
+    pub const fn attributes(kind: AnnotationType) -> Attributes {
+        match kind {
+            AnnotationType::Keyword => Attributes::BOLD,
+            AnnotationType::Comment => Attributes::ITALIC,
+            _ => Attributes::NONE,
+        }
+    }
+}
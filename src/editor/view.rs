@@ -1,64 +1,1580 @@
-use super::terminal::{Size, Terminal};
-use std::io::Error;
+use super::Location;
+use super::annotatedstring::{AnnotatedString, Annotation, AnnotationType};
+use super::command::Move;
+use super::filetype::FileType;
+use super::spell::Dictionary;
+use super::statusbar::DocumentStatus;
+use super::terminal::{Position, Size, Terminal};
+use core::cmp::min;
+use core::ops::Range;
+use std::fs::metadata;
+use std::io::{Error, ErrorKind};
+use std::rc::Rc;
 
 mod buffer;
-use buffer::Buffer;
+mod highlight;
+mod line;
+mod query;
+mod undostack;
+use buffer::{Buffer, BufferStats, RegexMatch};
+pub use buffer::{CaseTransform, SearchDirection};
+use highlight::Highlighter;
+use line::Line;
+pub use query::Query;
+use regex::Regex;
+use undostack::UndoList;
+use unicode_width::{UnicodeWidthChar, UnicodeWidthStr};
+
+#[derive(Clone, Copy, Default, PartialEq, Eq)]
+pub enum LineNumbers {
+    #[default]
+    Off,
+    Absolute,
+    Relative,
+}
+
+impl LineNumbers {
+    const fn next(self) -> Self {
+        match self {
+            Self::Off => Self::Absolute,
+            Self::Absolute => Self::Relative,
+            Self::Relative => Self::Off,
+        }
+    }
+}
+
+/// Where `place_caret_line` puts the caret's line on screen.
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum CaretPlacement {
+    Top,
+    Center,
+    Bottom,
+}
 
 const NAME: &str = env!("CARGO_PKG_NAME");
 const VERSION: &str = env!("CARGO_PKG_VERSION");
+const DEFAULT_TAB_WIDTH: usize = 4;
+const DEFAULT_SCROLL_LINES: usize = 3;
+/// The brackets and quotes that `auto_pairs` closes, as opener and closer.
+const PAIRS: [(char, char); 5] = [('(', ')'), ('[', ']'), ('{', '}'), ('"', '"'), ('\'', '\'')];
+/// Files larger than this are opened read-only and read on demand.
+const LAZY_LOAD_SIZE: u64 = 64 * 1024 * 1024;
+
+/// Editing and display preferences.
+#[derive(Clone, Copy)]
+#[allow(clippy::struct_excessive_bools)]
+pub struct Options {
+    pub tab_width: usize,
+    pub expand_tab: bool,
+    pub smart_backspace: bool,
+    pub auto_pairs: bool,
+    pub show_whitespace: bool,
+    /// How many lines one step of the mouse wheel scrolls.
+    pub scroll_lines: usize,
+    /// How many lines to keep between the caret and the top and bottom of
+    /// the screen, where the document allows.
+    pub scroll_off: usize,
+    pub scrollbar: bool,
+    /// Whether saving first copies the file on disk to `<file>~`.
+    pub backup: bool,
+    /// Whether saving strips spaces and tabs from the ends of lines.
+    pub trim_trailing_whitespace: bool,
+    /// Whether saving drops blank lines at the end and ends the file with
+    /// exactly one line ending.
+    pub ensure_final_newline: bool,
+    /// Whether saving ends the file with a line ending, leaving any blank
+    /// lines before it alone.
+    pub insert_final_newline: bool,
+    /// The display column, counted from 0, that is tinted on every line.
+    pub color_column: Option<usize>,
+}
+
+impl Default for Options {
+    fn default() -> Self {
+        Self {
+            tab_width: DEFAULT_TAB_WIDTH,
+            expand_tab: false,
+            smart_backspace: false,
+            auto_pairs: false,
+            show_whitespace: false,
+            scroll_lines: DEFAULT_SCROLL_LINES,
+            scroll_off: 0,
+            scrollbar: false,
+            backup: false,
+            trim_trailing_whitespace: false,
+            ensure_final_newline: false,
+            insert_final_newline: false,
+            color_column: None,
+        }
+    }
+}
+
+/// Lines `start..=end` shown as a single summary row.
+#[derive(Clone, Copy, PartialEq, Eq)]
+struct FoldRange {
+    start: usize,
+    end: usize,
+}
+
+impl FoldRange {
+    const fn contains(self, line_idx: usize) -> bool {
+        self.start <= line_idx && line_idx <= self.end
+    }
+}
+
+/// Where a view is drawn on screen and which part of the buffer it shows.
+/// Panes on the same buffer each keep their own and swap it in to render.
+#[derive(Clone, Default)]
+pub struct Viewport {
+    origin_row: usize,
+    size: Size,
+    dirty_rows: Vec<bool>,
+    scroll_offset: Location,
+    caret: Location,
+    /// Further carets that edits and moves apply to as well, in no
+    /// particular order.
+    extra_carets: Vec<Location>,
+    desired_x: usize,
+    /// The bracket under the caret and its partner, if it has one.
+    brackets: Option<(Location, Option<Location>)>,
+}
+
+impl Viewport {
+    pub fn needs_redraw(&self) -> bool {
+        self.dirty_rows.contains(&true)
+    }
+
+    pub fn mark_all_dirty(&mut self) {
+        self.dirty_rows.clear();
+        self.dirty_rows.resize(self.size.height, true);
+    }
+}
 
 pub struct View {
     buffer: Buffer,
-    size: Size,
-    needs_redraw: bool,
+    viewport: Viewport,
+    line_numbers: LineNumbers,
+    soft_wrap: bool,
+    search_query: Option<Query>,
+    selected_match: Option<Location>,
+    selection_anchor: Option<Location>,
+    /// Folded regions, in document order and never overlapping.
+    folds: Vec<FoldRange>,
+    options: Options,
+    highlighter: Highlighter,
+    /// How many block comments deep each line starts, for the lines from the
+    /// top of the document that have been highlighted so far.
+    line_states: Vec<usize>,
+    /// The word list misspellings are underlined from, while spell checking
+    /// is on.
+    dictionary: Option<Rc<Dictionary>>,
+    spelling_cycle: Option<SpellingCycle>,
+}
+
+/// Where `next_spelling_suggestion` is in the suggestions for a word.
+struct SpellingCycle {
+    start: Location,
+    /// The suggestions, followed by the word as it was first spelled.
+    words: Vec<String>,
+    idx: usize,
 }
 
 impl Default for View {
     fn default() -> Self {
+        let size = Terminal::size().unwrap_or_default();
         Self {
             buffer: Buffer::default(),
-            needs_redraw: true,
-            size: Terminal::size().unwrap_or_default(),
+            viewport: Viewport {
+                dirty_rows: vec![true; size.height],
+                size,
+                ..Viewport::default()
+            },
+            line_numbers: LineNumbers::Off,
+            soft_wrap: false,
+            search_query: None,
+            selected_match: None,
+            selection_anchor: None,
+            folds: Vec::new(),
+            options: Options::default(),
+            highlighter: Highlighter::default(),
+            line_states: Vec::new(),
+            dictionary: None,
+            spelling_cycle: None,
         }
     }
 }
 
 impl View {
-    pub fn resize(&mut self, to: Size) {
-        self.size = to;
-        self.needs_redraw = true;
+    /// Places the view at `origin_row` with the given size.
+    pub fn place(&mut self, origin_row: usize, size: Size) {
+        if (origin_row, size) != (self.viewport.origin_row, self.viewport.size) {
+            self.viewport.origin_row = origin_row;
+            self.viewport.size = size;
+            self.mark_all_dirty();
+        }
+    }
+
+    pub const fn viewport(&self) -> &Viewport {
+        &self.viewport
+    }
+
+    /// Exchanges the current viewport for `viewport`, clamping its caret in
+    /// case the buffer shrank while it was swapped out.
+    pub fn swap_viewport(&mut self, viewport: &mut Viewport) {
+        core::mem::swap(&mut self.viewport, viewport);
+        self.viewport.caret = self.buffer.clamp_location(self.viewport.caret);
+        for caret in &mut self.viewport.extra_carets {
+            *caret = self.buffer.clamp_location(*caret);
+        }
+    }
+
+    pub const fn caret(&self) -> Location {
+        self.viewport.caret
+    }
+
+    pub fn set_caret(&mut self, to: Location) {
+        self.update_caret(to);
+        self.viewport.desired_x = to.x;
+    }
+
+    /// Puts the caret back where it was in an earlier session, as far as the
+    /// file still reaches.
+    pub fn restore_caret(&mut self, to: Location) {
+        let to = self.buffer.clamp_location(to);
+        self.set_caret(to);
+    }
+
+    fn update_caret(&mut self, to: Location) {
+        if (self.line_numbers == LineNumbers::Relative && to.y != self.viewport.caret.y)
+            || (self.selection_anchor.is_some() && to != self.viewport.caret)
+        {
+            self.mark_all_dirty();
+        }
+        self.viewport.caret = to;
+    }
+
+    pub fn has_extra_carets(&self) -> bool {
+        !self.viewport.extra_carets.is_empty()
+    }
+
+    pub fn clear_extra_carets(&mut self) {
+        if self.has_extra_carets() {
+            self.viewport.extra_carets.clear();
+            self.mark_all_dirty();
+        }
+    }
+
+    /// All carets in document order, along with the index of the main one.
+    fn carets(&self) -> (Vec<Location>, usize) {
+        let mut carets: Vec<Location> = self
+            .viewport
+            .extra_carets
+            .iter()
+            .map(|&caret| self.buffer.clamp_location(caret))
+            .collect();
+        carets.push(self.viewport.caret);
+        carets.sort_unstable_by_key(|caret| (caret.y, caret.x));
+        let main = carets
+            .iter()
+            .position(|&caret| caret == self.viewport.caret)
+            .unwrap_or(0);
+        (carets, main)
+    }
+
+    /// Makes `carets[main]` the main caret and the rest extra carets,
+    /// dropping any that ended up in the same place.
+    fn set_carets(&mut self, carets: &[Location], main: usize) {
+        let caret = carets.get(main).copied().unwrap_or(self.viewport.caret);
+        let mut extra_carets: Vec<Location> = Vec::new();
+        for &extra in carets {
+            if extra != caret && !extra_carets.contains(&extra) {
+                extra_carets.push(extra);
+            }
+        }
+        self.set_caret(caret);
+        self.viewport.extra_carets = extra_carets;
+        self.mark_all_dirty();
+    }
+
+    /// Adds a caret in the same column on the line below the lowest caret.
+    pub fn add_caret_below(&mut self) {
+        let (mut carets, main) = self.carets();
+        let Some(&last) = carets.last() else {
+            return;
+        };
+        let y = last.y.saturating_add(1);
+        if y >= self.buffer.line_count() {
+            return;
+        }
+        let x = min(self.viewport.desired_x, self.buffer.line_len(y));
+        carets.push(Location { x, y });
+        self.set_carets(&carets, main);
+    }
+
+    /// Adds a caret on the next occurrence of the word under the main caret
+    /// that has none yet, at the same offset into the word. Returns whether
+    /// there was one.
+    pub fn add_caret_at_next_match(&mut self) -> bool {
+        let caret = self.viewport.caret;
+        let Some((start, end)) = self.buffer.word_at(caret) else {
+            return false;
+        };
+        let Ok(mut query) = Query::parse(&self.buffer.text_range(start, end)) else {
+            return false;
+        };
+        query.whole_word = true;
+        let offset = caret.x.saturating_sub(start.x);
+        let (mut carets, main) = self.carets();
+        let mut from = start;
+        loop {
+            from.x = from.x.saturating_add(1);
+            let Some((found, _)) = self.find_wrapping(&query, from, SearchDirection::Forward)
+            else {
+                return false;
+            };
+            let new_caret = Location {
+                x: found.x.saturating_add(offset),
+                y: found.y,
+            };
+            if new_caret == caret {
+                return false;
+            }
+            if !carets.contains(&new_caret) {
+                carets.push(new_caret);
+                self.set_carets(&carets, main);
+                return true;
+            }
+            from = found;
+        }
+    }
+
+    fn fold_at(&self, line_idx: usize) -> Option<FoldRange> {
+        self.folds
+            .iter()
+            .find(|fold| fold.contains(line_idx))
+            .copied()
+    }
+
+    /// Unfolds the fold under the caret, or else folds the indented block
+    /// around it. Returns `false` if there was nothing to fold.
+    pub fn toggle_fold(&mut self) -> bool {
+        let caret = self.viewport.caret;
+        if let Some(fold) = self.fold_at(caret.y) {
+            self.folds.retain(|&other| other != fold);
+        } else if let Some(block) = self.buffer.indented_block(caret.y) {
+            let fold = FoldRange {
+                start: *block.start(),
+                end: *block.end(),
+            };
+            self.folds
+                .retain(|other| other.end < fold.start || other.start > fold.end);
+            let idx = self.folds.partition_point(|other| other.start < fold.start);
+            self.folds.insert(idx, fold);
+            self.set_caret(Location {
+                x: 0,
+                y: fold.start,
+            });
+        } else {
+            return false;
+        }
+        self.mark_all_dirty();
+        true
+    }
+
+    fn unfold_at(&mut self, line_idx: usize) {
+        if let Some(fold) = self.fold_at(line_idx) {
+            self.folds.retain(|&other| other != fold);
+            self.mark_all_dirty();
+        }
+    }
+
+    /// Drops all folds, for edits that may move lines around anywhere.
+    fn clear_folds(&mut self) {
+        if !self.folds.is_empty() {
+            self.folds.clear();
+            self.mark_all_dirty();
+        }
+    }
+
+    /// Applies `edit` at every caret as one undo step. Carets are visited
+    /// from the bottom up, so an edit never moves text before the carets
+    /// still to come; the ones already done are kept as distances from the
+    /// end of the document, which edits above them don't change.
+    fn edit_at_carets(&mut self, edit: impl Fn(&mut Self)) {
+        if !self.has_extra_carets() {
+            self.edit_with_folds(&edit);
+            return;
+        }
+        let (carets, main) = self.carets();
+        let checkpoint = self.buffer.undo_checkpoint();
+        let mut from_end = Vec::with_capacity(carets.len());
+        for &caret in carets.iter().rev() {
+            self.set_caret(caret);
+            self.edit_with_folds(&edit);
+            from_end.push(self.distance_from_end(self.viewport.caret));
+        }
+        self.buffer.merge_undo_steps(checkpoint);
+        let carets: Vec<Location> = from_end
+            .into_iter()
+            .rev()
+            .map(|distance| self.location_from_end(distance))
+            .collect();
+        self.set_carets(&carets, main);
+    }
+
+    /// Runs `edit` at the caret, unfolding the fold it happens in and moving
+    /// the folds below it along with any lines it added or removed.
+    fn edit_with_folds<T>(&mut self, edit: impl FnOnce(&mut Self) -> T) -> T {
+        if self.folds.is_empty() {
+            return edit(self);
+        }
+        let line_idx = self.viewport.caret.y;
+        let line_count = self.buffer.line_count();
+        self.unfold_at(line_idx);
+        let result = edit(self);
+        let added = self.buffer.line_count().saturating_sub(line_count);
+        let removed = line_count.saturating_sub(self.buffer.line_count());
+        for fold in &mut self.folds {
+            if fold.start > line_idx {
+                fold.start = fold.start.saturating_add(added).saturating_sub(removed);
+                fold.end = fold.end.saturating_add(added).saturating_sub(removed);
+            }
+        }
+        // Joining a line onto the end of a fold leaves the caret inside it.
+        self.unfold_at(self.viewport.caret.y);
+        result
+    }
+
+    /// How many lines `at` is before the end of the document, and how many
+    /// graphemes before the end of its line.
+    fn distance_from_end(&self, at: Location) -> (usize, usize) {
+        (
+            self.buffer.line_count().saturating_sub(at.y),
+            self.buffer.line_len(at.y).saturating_sub(at.x),
+        )
+    }
+
+    fn location_from_end(&self, (lines, graphemes): (usize, usize)) -> Location {
+        let y = self.buffer.line_count().saturating_sub(lines);
+        Location {
+            x: self.buffer.line_len(y).saturating_sub(graphemes),
+            y,
+        }
+    }
+
+    /// Moves every caret. Jumps across the document leave only the main
+    /// caret behind, as the others would all land in the same place.
+    pub fn move_caret(&mut self, direction: Move) {
+        if matches!(direction, Move::StartOfDocument | Move::EndOfDocument) {
+            self.push_jump(self.viewport.caret);
+        }
+        if !self.has_extra_carets() {
+            self.move_single_caret(direction);
+            return;
+        }
+        if matches!(
+            direction,
+            Move::PageUp | Move::PageDown | Move::StartOfDocument | Move::EndOfDocument
+        ) {
+            self.clear_extra_carets();
+            self.move_single_caret(direction);
+            return;
+        }
+        let (mut carets, main) = self.carets();
+        let mut desired_x = self.viewport.desired_x;
+        for (idx, caret) in carets.iter_mut().enumerate() {
+            self.viewport.caret = *caret;
+            self.viewport.desired_x = if idx == main { desired_x } else { caret.x };
+            self.move_single_caret(direction);
+            *caret = self.viewport.caret;
+            if idx == main {
+                desired_x = self.viewport.desired_x;
+            }
+        }
+        self.set_carets(&carets, main);
+        if direction.is_vertical() {
+            self.viewport.desired_x = desired_x;
+        }
+    }
+
+    fn move_single_caret(&mut self, direction: Move) {
+        let Location { mut x, mut y } = self.viewport.caret;
+        match direction {
+            Move::Up => {
+                y = y.saturating_sub(1);
+            }
+            Move::Down => {
+                y = y.saturating_add(1);
+            }
+            Move::Left => {
+                x = x.saturating_sub(1);
+            }
+            Move::Right => {
+                x = x.saturating_add(1);
+            }
+            Move::WordLeft => {
+                Location { x, y } = self
+                    .buffer
+                    .word_boundary(self.viewport.caret, SearchDirection::Backward);
+            }
+            Move::WordRight => {
+                Location { x, y } = self
+                    .buffer
+                    .word_boundary(self.viewport.caret, SearchDirection::Forward);
+            }
+            Move::EndOfLine => {
+                x = self.buffer.line_len(y);
+            }
+            Move::StartOfLine => {
+                x = 0;
+            }
+            Move::StartOfText => {
+                let first_non_blank = self.buffer.first_non_blank(y);
+                x = if x == first_non_blank {
+                    0
+                } else {
+                    first_non_blank
+                };
+            }
+            Move::PageUp => {
+                y = y.saturating_sub(self.viewport.size.height);
+            }
+            Move::PageDown => {
+                let last_line = self.buffer.line_count().saturating_sub(1);
+                y = min(y.saturating_add(self.viewport.size.height), last_line);
+            }
+            Move::StartOfDocument => {
+                y = 0;
+            }
+            Move::EndOfDocument => {
+                y = self.buffer.line_count().saturating_sub(1);
+            }
+        }
+        y = min(y, self.buffer.line_count());
+        // Step over folded lines onto the line after the fold going down, or
+        // onto its summary row otherwise.
+        if let Some(fold) = self.fold_at(y)
+            && y != fold.start
+        {
+            let below = fold.end.saturating_add(1);
+            y = if y > self.viewport.caret.y && below < self.buffer.line_count() {
+                below
+            } else {
+                fold.start
+            };
+        }
+        if matches!(direction, Move::PageUp | Move::PageDown) {
+            // Scroll by as much as the caret moved so it keeps its place on screen.
+            self.viewport.scroll_offset.y = if y < self.viewport.caret.y {
+                self.viewport
+                    .scroll_offset
+                    .y
+                    .saturating_sub(self.viewport.caret.y.saturating_sub(y))
+            } else {
+                self.viewport
+                    .scroll_offset
+                    .y
+                    .saturating_add(y.saturating_sub(self.viewport.caret.y))
+            };
+            self.mark_all_dirty();
+        }
+        if direction.is_vertical() {
+            x = min(self.viewport.desired_x, self.buffer.line_len(y));
+        } else {
+            x = min(x, self.buffer.line_len(y));
+            self.viewport.desired_x = x;
+        }
+        self.update_caret(Location { x, y });
+    }
+
+    pub fn toggle_selection(&mut self) {
+        self.selection_anchor = if self.selection_anchor.is_some() {
+            None
+        } else {
+            Some(self.viewport.caret)
+        };
+        self.mark_all_dirty();
+    }
+
+    pub fn clear_selection(&mut self) {
+        if self.selection_anchor.take().is_some() {
+            self.mark_all_dirty();
+        }
+    }
+
+    /// The selected range in document order, or `None` if nothing is selected.
+    fn selection(&self) -> Option<(Location, Location)> {
+        let anchor = self.buffer.clamp_location(self.selection_anchor?);
+        let caret = self.buffer.clamp_location(self.viewport.caret);
+        let (start, end) = if (anchor.y, anchor.x) <= (caret.y, caret.x) {
+            (anchor, caret)
+        } else {
+            (caret, anchor)
+        };
+        (start != end).then_some((start, end))
+    }
+
+    pub fn copy_selection(&self) -> Option<String> {
+        let (start, end) = self.selection()?;
+        Some(self.buffer.text_range(start, end))
+    }
+
+    /// Removes the selected text and returns it, leaving the caret where the
+    /// selection started.
+    pub fn cut_selection(&mut self) -> Option<String> {
+        let (start, end) = self.selection()?;
+        if start.y != end.y {
+            self.clear_folds();
+        }
+        let text = self.buffer.delete_range(start, end);
+        self.selection_anchor = None;
+        self.set_caret(start);
+        self.mark_all_dirty();
+        Some(text)
+    }
+
+    /// Moves the caret to the start of the 1-based `line_number`, clamped to
+    /// the last line.
+    pub fn go_to_line(&mut self, line_number: usize) {
+        let last_line = self.buffer.line_count().saturating_sub(1);
+        self.jump_to(Location {
+            x: 0,
+            y: min(line_number.saturating_sub(1), last_line),
+        });
+    }
+
+    /// Moves the caret to the partner of the bracket it is on, if any.
+    pub fn jump_to_matching_bracket(&mut self) {
+        if let Some(location) = self.buffer.matching_bracket(self.viewport.caret) {
+            self.jump_to(location);
+        }
+    }
+
+    /// Moves the caret to `to`, remembering where it was in the jump list.
+    fn jump_to(&mut self, to: Location) {
+        self.push_jump(self.viewport.caret);
+        self.set_caret(to);
+    }
+
+    /// Remembers `from` in the jump list, as a place the caret jumped away
+    /// from.
+    pub fn push_jump(&mut self, from: Location) {
+        self.buffer.push_jump(from);
+    }
+
+    pub fn set_mark(&mut self, name: char) {
+        self.buffer.set_mark(name, self.viewport.caret);
+    }
+
+    /// Moves the caret to mark `name`, returning whether it is set.
+    pub fn jump_to_mark(&mut self, name: char) -> bool {
+        let Some(at) = self.buffer.mark(name) else {
+            return false;
+        };
+        self.jump_to(at);
+        true
+    }
+
+    /// Goes back to where the caret was before the last jump, returning
+    /// whether there was one.
+    pub fn jump_back(&mut self) -> bool {
+        let Some(to) = self.buffer.jump_back(self.viewport.caret) else {
+            return false;
+        };
+        self.set_caret(to);
+        true
+    }
+
+    /// Undoes a `jump_back`, returning whether there was one.
+    pub fn jump_forward(&mut self) -> bool {
+        let Some(to) = self.buffer.jump_forward() else {
+            return false;
+        };
+        self.set_caret(to);
+        true
+    }
+
+    pub const fn options(&self) -> Options {
+        self.options
+    }
+
+    pub fn set_options(&mut self, options: Options) {
+        self.options = options;
+        self.mark_all_dirty();
+    }
+
+    /// Turns spell checking on with `dictionary`, or off with `None`.
+    pub fn set_dictionary(&mut self, dictionary: Option<Rc<Dictionary>>) {
+        self.dictionary = dictionary;
+        self.spelling_cycle = None;
+        self.mark_all_dirty();
+    }
+
+    pub const fn spell_check(&self) -> bool {
+        self.dictionary.is_some()
+    }
+
+    pub fn set_line_numbers(&mut self, line_numbers: LineNumbers) {
+        self.line_numbers = line_numbers;
+        self.mark_all_dirty();
+    }
+
+    pub fn cycle_line_numbers(&mut self) {
+        self.line_numbers = self.line_numbers.next();
+        self.mark_all_dirty();
+    }
+
+    fn gutter_width(&self) -> usize {
+        if self.line_numbers == LineNumbers::Off {
+            0
+        } else {
+            self.buffer.line_count().to_string().len().saturating_add(1)
+        }
+    }
+
+    fn text_width(&self) -> usize {
+        let scrollbar_width = usize::from(self.scrollbar_thumb().is_some());
+        self.viewport
+            .size
+            .width
+            .saturating_sub(self.gutter_width())
+            .saturating_sub(scrollbar_width)
+    }
+
+    /// The screen rows of the scrollbar's thumb, which stands for the lines
+    /// on screen, or `None` if the scrollbar is off or the whole document
+    /// fits on screen.
+    fn scrollbar_thumb(&self) -> Option<Range<usize>> {
+        let height = self.viewport.size.height;
+        let line_count = self.buffer.line_count();
+        if !self.options.scrollbar || line_count <= height {
+            return None;
+        }
+        let scaled = |rows: usize| {
+            rows.saturating_mul(height)
+                .checked_div(line_count)
+                .unwrap_or(0)
+        };
+        let len = scaled(height).max(1);
+        let start = min(
+            scaled(self.viewport.scroll_offset.y),
+            height.saturating_sub(len),
+        );
+        Some(start..start.saturating_add(len))
+    }
+
+    pub fn scroll_into_view(&mut self) {
+        self.buffer
+            .ensure_resident(self.viewport.caret.y..self.viewport.caret.y.saturating_add(1));
+        if self.soft_wrap {
+            self.scroll_wrapped_into_view();
+            return;
+        }
+        let height = self.viewport.size.height;
+        let width = self.text_width();
+        let Position { col, row } = self.text_location_to_position(self.viewport.caret);
+        let last_line = self.buffer.line_count().saturating_sub(1);
+        let above = min(self.scroll_off(), row);
+        let below = min(self.scroll_off(), last_line.saturating_sub(row));
+        if !self.folds.is_empty() {
+            self.scroll_rows_into_view();
+        } else if row < self.viewport.scroll_offset.y.saturating_add(above) {
+            self.viewport.scroll_offset.y = row.saturating_sub(above);
+            self.mark_all_dirty();
+        } else if row.saturating_add(below) >= self.viewport.scroll_offset.y.saturating_add(height)
+        {
+            self.viewport.scroll_offset.y = row
+                .saturating_add(below)
+                .saturating_sub(height)
+                .saturating_add(1);
+            self.mark_all_dirty();
+        }
+        if col < self.viewport.scroll_offset.x {
+            self.viewport.scroll_offset.x = col;
+            self.mark_all_dirty();
+        } else if col >= self.viewport.scroll_offset.x.saturating_add(width) {
+            self.viewport.scroll_offset.x = col.saturating_sub(width).saturating_add(1);
+            self.mark_all_dirty();
+        }
+    }
+
+    /// With soft wrap the screen always starts at a whole line, so scroll
+    /// by lines until the caret's wrapped row fits.
+    fn scroll_wrapped_into_view(&mut self) {
+        if self.viewport.scroll_offset.x != 0 {
+            self.viewport.scroll_offset.x = 0;
+            self.mark_all_dirty();
+        }
+        self.scroll_rows_into_view();
+    }
+
+    /// Scrolls by whole lines until the caret's screen row fits, for when
+    /// lines and screen rows don't match up one to one.
+    fn scroll_rows_into_view(&mut self) {
+        let margin = self.scroll_off();
+        if self.viewport.caret.y < self.viewport.scroll_offset.y {
+            self.viewport.scroll_offset.y = self.viewport.caret.y;
+            self.mark_all_dirty();
+        }
+        while self.viewport.scroll_offset.y > 0 && self.caret_screen_position().row < margin {
+            self.viewport.scroll_offset.y = self.viewport.scroll_offset.y.saturating_sub(1);
+            self.mark_all_dirty();
+        }
+        let next_line = self.viewport.caret.y.saturating_add(1);
+        let lines_below =
+            next_line..min(next_line.saturating_add(margin), self.buffer.line_count());
+        let below = min(margin, self.rows_taken(lines_below));
+        while self.viewport.scroll_offset.y < self.viewport.caret.y
+            && self.caret_screen_position().row.saturating_add(below) >= self.viewport.size.height
+        {
+            self.viewport.scroll_offset.y = self.viewport.scroll_offset.y.saturating_add(1);
+            self.mark_all_dirty();
+        }
+    }
+
+    /// The `scroll_off` margin, cut down to what fits on screen with the
+    /// caret between its two halves.
+    #[allow(clippy::integer_division)]
+    fn scroll_off(&self) -> usize {
+        min(
+            self.options.scroll_off,
+            self.viewport.size.height.saturating_sub(1) / 2,
+        )
+    }
+
+    pub fn toggle_soft_wrap(&mut self) {
+        self.set_soft_wrap(!self.soft_wrap);
+    }
+
+    pub fn set_soft_wrap(&mut self, soft_wrap: bool) {
+        self.soft_wrap = soft_wrap;
+        self.viewport.scroll_offset.x = 0;
+        self.mark_all_dirty();
+    }
+
+    pub fn caret_position(&self) -> Position {
+        let Size { height, width } = self.viewport.size;
+        let Position { col, row } = self.caret_screen_position();
+        Position {
+            col: min(
+                col.saturating_add(self.gutter_width()),
+                width.saturating_sub(1),
+            ),
+            row: min(row, height.saturating_sub(1)).saturating_add(self.viewport.origin_row),
+        }
+    }
+
+    /// The caret's row and column relative to the top-left of the text area,
+    /// before clamping to the screen.
+    fn caret_screen_position(&self) -> Position {
+        let col = self.text_location_to_position(self.viewport.caret).col;
+        let rows_above = self.rows_taken(self.viewport.scroll_offset.y..self.viewport.caret.y);
+        if self.fold_at(self.viewport.caret.y).is_some() {
+            return Position {
+                col: 0,
+                row: rows_above,
+            };
+        }
+        let (row_in_line, row_start) = self.buffer.line(self.viewport.caret.y).map_or(
+            (0, self.viewport.scroll_offset.x),
+            |line| {
+                let rows = self.visual_rows(line);
+                let row_in_line = rows.iter().rposition(|row| row.start <= col).unwrap_or(0);
+                let row_start = rows.get(row_in_line).map_or(0, |row| row.start);
+                (row_in_line, row_start)
+            },
+        );
+        Position {
+            col: col.saturating_sub(row_start),
+            row: rows_above.saturating_add(row_in_line),
+        }
+    }
+
+    /// How many screen rows the given lines take up.
+    fn rows_taken(&self, lines: Range<usize>) -> usize {
+        lines
+            .map(|line_idx| {
+                self.buffer
+                    .line(line_idx)
+                    .map_or(1, |line| self.line_rows(line_idx, line).len())
+            })
+            .sum()
+    }
+
+    /// Scrolls so that the caret's line is at the top, middle or bottom of
+    /// the screen, short of the `scroll_off` margin. The caret stays put,
+    /// and no rows past the end of the document come into view.
+    #[allow(clippy::integer_division)]
+    pub fn place_caret_line(&mut self, placement: CaretPlacement) {
+        let height = self.viewport.size.height;
+        let margin = self.scroll_off();
+        let rows_above = match placement {
+            CaretPlacement::Top => margin,
+            CaretPlacement::Center => height.saturating_sub(1) / 2,
+            CaretPlacement::Bottom => height.saturating_sub(1).saturating_sub(margin),
+        };
+        let caret_y = self.viewport.caret.y;
+        let mut top = caret_y;
+        let mut rows: usize = 0;
+        while let Some(above) = top.checked_sub(1) {
+            rows = rows.saturating_add(self.rows_taken(above..top));
+            if rows > rows_above {
+                break;
+            }
+            top = above;
+        }
+        if let Some(fold) = self.fold_at(top)
+            && top != fold.start
+        {
+            top = fold.end.saturating_add(1);
+        }
+        let line_count = self.buffer.line_count();
+        let mut last_top = line_count;
+        let mut rows: usize = 0;
+        while let Some(above) = last_top.checked_sub(1) {
+            rows = rows.saturating_add(self.rows_taken(above..last_top));
+            if rows > height {
+                break;
+            }
+            last_top = above;
+        }
+        let top = min(top, last_top);
+        if top != self.viewport.scroll_offset.y {
+            self.viewport.scroll_offset.y = top;
+            self.buffer.ensure_resident(top..top.saturating_add(height));
+            self.mark_all_dirty();
+        }
+    }
+
+    pub fn scroll_up(&mut self) {
+        let top = self
+            .viewport
+            .scroll_offset
+            .y
+            .saturating_sub(self.options.scroll_lines);
+        self.scroll_to(top);
+    }
+
+    pub fn scroll_down(&mut self) {
+        let last_line = self.buffer.line_count().saturating_sub(1);
+        let top = min(
+            self.viewport
+                .scroll_offset
+                .y
+                .saturating_add(self.options.scroll_lines),
+            last_line,
+        );
+        self.scroll_to(top);
+    }
+
+    /// Shows the buffer from line `top` down. The caret stays where it is
+    /// unless that would take it off screen, in which case it moves to the
+    /// nearest visible line.
+    fn scroll_to(&mut self, top: usize) {
+        if top == self.viewport.scroll_offset.y {
+            return;
+        }
+        self.viewport.scroll_offset.y = top;
+        self.buffer
+            .ensure_resident(top..top.saturating_add(self.viewport.size.height));
+        // Keep the caret out of the scroll_off margins, except where they
+        // run into either end of the document.
+        let margin = self.scroll_off();
+        let last_line = self.buffer.line_count().saturating_sub(1);
+        let first = if top == 0 {
+            0
+        } else {
+            top.saturating_add(margin)
+        };
+        let mut y = self.viewport.caret.y.max(min(first, last_line));
+        while y > top
+            && self
+                .rows_taken(top..y)
+                .saturating_add(min(margin, last_line.saturating_sub(y)))
+                >= self.viewport.size.height
+        {
+            y = y.saturating_sub(1);
+        }
+        if let Some(fold) = self.fold_at(y) {
+            y = fold.start;
+        }
+        if y != self.viewport.caret.y {
+            let x = min(self.viewport.desired_x, self.buffer.line_len(y));
+            self.update_caret(Location { x, y });
+        }
+        self.mark_all_dirty();
+    }
+
+    /// The column ranges of `line` shown on consecutive screen rows: a single
+    /// horizontally scrolled window, or every wrapped segment with soft wrap.
+    fn visual_rows(&self, line: &Line) -> Vec<Range<usize>> {
+        let width = self.text_width();
+        if !self.soft_wrap {
+            let left = self.viewport.scroll_offset.x;
+            return core::iter::once(left..left.saturating_add(width)).collect();
+        }
+        let tab_width = self.options.tab_width;
+        let starts: Vec<usize> = line
+            .wrap_points(width, tab_width)
+            .into_iter()
+            .map(|grapheme_idx| line.width_until(grapheme_idx, tab_width))
+            .collect();
+        starts
+            .iter()
+            .enumerate()
+            .map(|(idx, &start)| {
+                let end = starts
+                    .get(idx.saturating_add(1))
+                    .copied()
+                    .unwrap_or_else(|| start.saturating_add(width));
+                start..end
+            })
+            .collect()
+    }
+
+    /// The screen rows `line_idx` takes: none inside a fold, a single one for
+    /// the summary at its start, and its visual rows otherwise.
+    fn line_rows(&self, line_idx: usize, line: &Line) -> Vec<Range<usize>> {
+        match self.fold_at(line_idx) {
+            Some(fold) if fold.start == line_idx => {
+                let left = self.viewport.scroll_offset.x;
+                core::iter::once(left..left.saturating_add(self.text_width())).collect()
+            }
+            Some(_) => Vec::new(),
+            None => self.visual_rows(line),
+        }
+    }
+
+    /// Moves the caret to what is shown at `at` on screen.
+    pub fn click(&mut self, at: Position) {
+        let location = self.location_at(at);
+        self.clear_extra_carets();
+        self.set_caret(location);
+    }
+
+    /// The buffer location shown at a screen position. Positions past the end
+    /// of a row land on its last column, and rows below the buffer on its
+    /// last line.
+    fn location_at(&self, at: Position) -> Location {
+        let col = at.col.saturating_sub(self.gutter_width());
+        let mut rows_left = at.row.saturating_sub(self.viewport.origin_row);
+        let mut line_idx = self.viewport.scroll_offset.y;
+        while let Some(line) = self.buffer.line(line_idx) {
+            let rows = self.line_rows(line_idx, line);
+            if let Some(range) = rows.get(rows_left) {
+                let is_last_row = rows_left.saturating_add(1) == rows.len();
+                let column = if is_last_row {
+                    range.start.saturating_add(col)
+                } else {
+                    min(range.start.saturating_add(col), range.end.saturating_sub(1))
+                };
+                return Location {
+                    x: line.grapheme_idx_at_column(column, self.options.tab_width),
+                    y: line_idx,
+                };
+            }
+            rows_left = rows_left.saturating_sub(rows.len());
+            line_idx = line_idx.saturating_add(1);
+        }
+        self.buffer.clamp_location(Location {
+            x: 0,
+            y: self.buffer.line_count(),
+        })
+    }
+
+    fn text_location_to_position(&self, location: Location) -> Position {
+        let col = self.buffer.line(location.y).map_or(0, |line| {
+            line.width_until(location.x, self.options.tab_width)
+        });
+        Position {
+            col,
+            row: location.y,
+        }
+    }
+
+    pub const fn scroll_offset(&self) -> Location {
+        self.viewport.scroll_offset
+    }
+
+    pub fn set_scroll_offset(&mut self, to: Location) {
+        self.viewport.scroll_offset = to;
+        self.mark_all_dirty();
+    }
+
+    fn render_row(&self, at: usize, mut row: AnnotatedString) {
+        if self.viewport.dirty_rows.get(at).is_some_and(|&dirty| dirty) {
+            if self
+                .scrollbar_thumb()
+                .is_some_and(|thumb| thumb.contains(&at))
+            {
+                let thumb_col = self.viewport.size.width.saturating_sub(1);
+                let width = row.to_string().width();
+                row.push(&" ".repeat(thumb_col.saturating_sub(width)), None);
+                row.push(" ", Some(AnnotationType::ScrollbarThumb));
+            }
+            let result =
+                Terminal::print_annotated_row(at.saturating_add(self.viewport.origin_row), &row);
+            debug_assert!(result.is_ok(), "Failed to render line");
+        }
+    }
+
+    pub fn set_search_highlight(&mut self, query: &Query, selected_match: Location) {
+        self.search_query = Some(query.clone());
+        self.selected_match = Some(selected_match);
+        self.mark_all_dirty();
+    }
+
+    pub fn clear_search_highlight(&mut self) {
+        self.search_query = None;
+        self.selected_match = None;
+        self.mark_all_dirty();
+    }
+
+    fn search_annotations(&self, line_idx: usize) -> Vec<Annotation> {
+        let (Some(query), Some(line)) = (&self.search_query, self.buffer.line(line_idx)) else {
+            return Vec::new();
+        };
+        line.find_all(query)
+            .into_iter()
+            .map(|range| {
+                let is_selected = self.selected_match
+                    == Some(Location {
+                        x: range.start,
+                        y: line_idx,
+                    });
+                Annotation {
+                    kind: if is_selected {
+                        AnnotationType::SelectedMatch
+                    } else {
+                        AnnotationType::Match
+                    },
+                    start: range.start,
+                    end: range.end,
+                }
+            })
+            .collect()
+    }
+
+    fn selection_annotation(&self, line_idx: usize) -> Option<Annotation> {
+        let (start, end) = self.selection()?;
+        if line_idx < start.y || line_idx > end.y {
+            return None;
+        }
+        Some(Annotation {
+            kind: AnnotationType::Selection,
+            start: if line_idx == start.y { start.x } else { 0 },
+            end: if line_idx == end.y {
+                end.x
+            } else {
+                self.buffer.line_len(line_idx)
+            },
+        })
+    }
+
+    pub fn toggle_whitespace(&mut self) {
+        self.options.show_whitespace = !self.options.show_whitespace;
+        self.mark_all_dirty();
+    }
+
+    /// Marks trailing whitespace, and tabs that follow a space in the
+    /// indentation.
+    fn whitespace_annotations(line: &Line) -> Vec<Annotation> {
+        let text = line.as_str();
+        let mut annotations = Vec::new();
+        let indentation = &text[..text.len().saturating_sub(text.trim_start().len())];
+        let mut seen_space = false;
+        for (byte_idx, character) in indentation.char_indices() {
+            if character == ' ' {
+                seen_space = true;
+            } else if character == '\t'
+                && seen_space
+                && let Some(start) = line.byte_idx_to_grapheme_idx(byte_idx)
+            {
+                annotations.push(Annotation {
+                    kind: AnnotationType::MixedIndentation,
+                    start,
+                    end: start.saturating_add(1),
+                });
+            }
+        }
+        if let Some(start) = line.byte_idx_to_grapheme_idx(text.trim_end().len()) {
+            annotations.push(Annotation {
+                kind: AnnotationType::TrailingWhitespace,
+                start,
+                end: line.grapheme_count(),
+            });
+        }
+        annotations
+    }
+
+    pub fn needs_redraw(&self) -> bool {
+        self.viewport.needs_redraw()
+    }
+
+    pub fn mark_all_dirty(&mut self) {
+        self.viewport.mark_all_dirty();
+    }
+
+    /// Marks the screen row showing `line_idx` for redrawing. Wrapped lines
+    /// can change how many rows they take, and an edit can open or close a
+    /// block comment that colors the lines below, so those fall back to
+    /// redrawing everything from the line down.
+    fn mark_line_dirty(&mut self, line_idx: usize) {
+        let Some(row) = line_idx.checked_sub(self.viewport.scroll_offset.y) else {
+            return;
+        };
+        if self.soft_wrap || !self.folds.is_empty() {
+            self.mark_all_dirty();
+        } else if self.highlighter.spans_lines() {
+            for dirty in self.viewport.dirty_rows.iter_mut().skip(row) {
+                *dirty = true;
+            }
+        } else if let Some(dirty) = self.viewport.dirty_rows.get_mut(row) {
+            *dirty = true;
+        }
+    }
+
+    /// Finds the brackets to highlight for the current caret and marks the
+    /// rows whose highlight changed.
+    fn update_bracket_highlight(&mut self) {
+        let caret = self.viewport.caret;
+        let brackets = self
+            .buffer
+            .is_bracket(caret)
+            .then(|| (caret, self.buffer.matching_bracket(caret)));
+        if brackets == self.viewport.brackets {
+            return;
+        }
+        let changed: Vec<Location> = [self.viewport.brackets, brackets]
+            .into_iter()
+            .flatten()
+            .flat_map(|(at, partner)| core::iter::once(at).chain(partner))
+            .collect();
+        for location in changed {
+            if self.soft_wrap || !self.folds.is_empty() {
+                self.mark_all_dirty();
+            } else if let Some(row) = location.y.checked_sub(self.viewport.scroll_offset.y)
+                && let Some(dirty) = self.viewport.dirty_rows.get_mut(row)
+            {
+                *dirty = true;
+            }
+        }
+        self.viewport.brackets = brackets;
+    }
+
+    /// Tints the grapheme drawn at the color column. It loses its syntax
+    /// color there, but search matches and the selection still show.
+    fn color_column_annotation(&self, line: &Line) -> Option<Annotation> {
+        let column = self.options.color_column?;
+        let grapheme_idx = line.grapheme_idx_at_column(column, self.options.tab_width);
+        (grapheme_idx < line.grapheme_count()).then(|| Annotation {
+            kind: AnnotationType::ColorColumn,
+            start: grapheme_idx,
+            end: grapheme_idx.saturating_add(1),
+        })
+    }
+
+    fn extra_caret_annotations(&self, line_idx: usize) -> Vec<Annotation> {
+        self.viewport
+            .extra_carets
+            .iter()
+            .filter(|caret| caret.y == line_idx)
+            .map(|caret| Annotation {
+                kind: AnnotationType::ExtraCaret,
+                start: caret.x,
+                end: caret.x.saturating_add(1),
+            })
+            .collect()
     }
 
-    fn render_line(at: usize, line_text: &str) {
-        let result = Terminal::print_row(at, line_text);
-        debug_assert!(result.is_ok(), "Failes to render line");
+    /// Extends a row showing `columns` of `line` with the cells drawn past
+    /// its end: an extra caret sitting at the end of the line, and the color
+    /// column if the line ends before it.
+    fn pad_past_line_end(
+        &self,
+        row: &mut AnnotatedString,
+        line_idx: usize,
+        line: &Line,
+        columns: &Range<usize>,
+    ) {
+        let grapheme_count = line.grapheme_count();
+        let line_width = line.width_until(grapheme_count, self.options.tab_width);
+        let mut cells = Vec::new();
+        if self
+            .viewport
+            .extra_carets
+            .iter()
+            .any(|caret| caret.y == line_idx && caret.x >= grapheme_count)
+        {
+            cells.push((line_width, AnnotationType::ExtraCaret));
+        }
+        if let Some(column) = self.options.color_column
+            && cells.first().is_none_or(|&(taken, _)| column > taken)
+            && column >= line_width
+        {
+            cells.push((column, AnnotationType::ColorColumn));
+        }
+        let mut end = line_width.max(columns.start);
+        for (column, kind) in cells {
+            if columns.contains(&column) {
+                row.push(&" ".repeat(column.saturating_sub(end)), None);
+                row.push(" ", Some(kind));
+                end = column.saturating_add(1);
+            }
+        }
+    }
+
+    fn bracket_annotations(&self, line_idx: usize) -> Vec<Annotation> {
+        let Some((at, partner)) = self.viewport.brackets else {
+            return Vec::new();
+        };
+        let kind = if partner.is_some() {
+            AnnotationType::MatchingBracket
+        } else {
+            AnnotationType::UnmatchedBracket
+        };
+        core::iter::once(at)
+            .chain(partner)
+            .filter(|location| location.y == line_idx)
+            .map(|location| Annotation {
+                kind,
+                start: location.x,
+                end: location.x.saturating_add(1),
+            })
+            .collect()
+    }
+
+    /// How many block comments deep line `row` starts. The lines above it
+    /// are highlighted once and remembered until they are edited; a lazily
+    /// loaded buffer reads them in on the way.
+    fn block_comment_depth_at(&mut self, row: usize) -> usize {
+        if let Some(changed_row) = self.buffer.take_first_changed_row() {
+            // A line's own edits only affect the lines after it.
+            self.line_states.truncate(changed_row.saturating_add(1));
+        }
+        if !self.highlighter.spans_lines() {
+            return 0;
+        }
+        if self.line_states.is_empty() {
+            self.line_states.push(0);
+        }
+        while self.line_states.len() <= row {
+            let above = self.line_states.len().saturating_sub(1);
+            let depth = self.line_states.last().copied().unwrap_or_default();
+            self.buffer.ensure_resident(above..above.saturating_add(1));
+            let Some(line) = self.buffer.line(above) else {
+                break;
+            };
+            let mut highlighter = self.highlighter.resumed_at(depth);
+            highlighter.highlight_line(line);
+            self.line_states.push(highlighter.block_comment_depth());
+        }
+        self.line_states
+            .get(row)
+            .or_else(|| self.line_states.last())
+            .copied()
+            .unwrap_or_default()
+    }
+
+    /// Marks the words of `line` the dictionary doesn't know. In source code
+    /// only comments and strings, as found in `highlights`, are checked.
+    fn spelling_annotations(&self, line: &Line, highlights: &[Annotation]) -> Vec<Annotation> {
+        let Some(dictionary) = &self.dictionary else {
+            return Vec::new();
+        };
+        let is_code = self.buffer.file_type.line_comment().is_some();
+        line.words()
+            .filter(|(range, _)| {
+                !is_code
+                    || highlights.iter().any(|highlight| {
+                        matches!(
+                            highlight.kind,
+                            AnnotationType::Comment | AnnotationType::String
+                        ) && highlight.start <= range.start
+                            && range.end <= highlight.end
+                    })
+            })
+            .filter(|(_, word)| dictionary.is_misspelled(word))
+            .map(|(range, _)| Annotation {
+                kind: AnnotationType::Misspelled,
+                start: range.start,
+                end: range.end,
+            })
+            .collect()
+    }
+
+    /// Replaces the word under the caret with the next suggestion for it,
+    /// coming back to the original spelling after the last one. Returns
+    /// which word of how many it now is, the original being last, or `None`
+    /// if spell checking is off or there is nothing to suggest.
+    pub fn next_spelling_suggestion(&mut self) -> Option<(usize, usize)> {
+        let dictionary = self.dictionary.clone()?;
+        let (start, end) = self.buffer.word_at(self.viewport.caret)?;
+        let word = self.buffer.text_range(start, end);
+        let continues = self
+            .spelling_cycle
+            .as_ref()
+            .is_some_and(|cycle| cycle.start == start && cycle.words.get(cycle.idx) == Some(&word));
+        if !continues {
+            let mut words = dictionary.suggestions(&word);
+            if words.is_empty() {
+                return None;
+            }
+            words.push(word);
+            self.spelling_cycle = Some(SpellingCycle {
+                start,
+                idx: words.len().saturating_sub(1),
+                words,
+            });
+        }
+        let cycle = self.spelling_cycle.as_mut()?;
+        cycle.idx = cycle
+            .idx
+            .saturating_add(1)
+            .checked_rem(cycle.words.len())
+            .unwrap_or(0);
+        let replacement = cycle.words.get(cycle.idx)?.clone();
+        let position = (cycle.idx.saturating_add(1), cycle.words.len());
+        self.set_caret(start);
+        self.edit_with_folds(|view| {
+            view.buffer
+                .replace_range(start, end.x.saturating_sub(start.x), &replacement);
+            view.set_caret(start);
+        });
+        self.mark_line_dirty(start.y);
+        Some(position)
     }
 
     pub fn render(&mut self) {
-        if !self.needs_redraw {
+        self.update_bracket_highlight();
+        if !self.needs_redraw() {
             return;
         }
-        let Size { height, width } = self.size;
+        let height = self.viewport.size.height;
+        let width = self.text_width();
         if height == 0 || width == 0 {
             return;
         }
+        #[allow(clippy::integer_division)]
         let vertical_center = height / 3;
 
-        for current_row in 0..height {
-            if let Some(line) = self.buffer.lines.get(current_row) {
-                let truncated_line = if line.len() >= width {
-                    &line[0..width]
-                } else {
-                    line
-                };
-                Self::render_line(current_row, truncated_line);
-            } else if current_row == vertical_center && self.buffer.is_empty() {
-                Self::render_line(current_row, &Self::build_welcome_message(width));
+        // Block comments may open above the screen.
+        let top = self.viewport.scroll_offset.y;
+        let depth = self.block_comment_depth_at(top);
+        let mut highlighter = self.highlighter.resumed_at(depth);
+        self.buffer.ensure_resident(top..top.saturating_add(height));
+
+        let mut current_row = 0;
+        let mut line_idx = self.viewport.scroll_offset.y;
+        while current_row < height {
+            if let Some(line) = self.buffer.line(line_idx)
+                && let Some(fold) = self.fold_at(line_idx)
+            {
+                // Folded lines still go through the highlighter, as they may
+                // open or close a block comment.
+                highlighter.highlight_line(line);
+                if line_idx == fold.start {
+                    let row = self.build_fold_row(fold, line);
+                    self.render_row(current_row, row);
+                    current_row = current_row.saturating_add(1);
+                }
+            } else if let Some(line) = self.buffer.line(line_idx) {
+                let mut annotations = highlighter.highlight_line(line);
+                annotations.extend(self.spelling_annotations(line, &annotations));
+                annotations.extend(self.color_column_annotation(line));
+                if self.options.show_whitespace {
+                    annotations.extend(Self::whitespace_annotations(line));
+                }
+                annotations.extend(self.search_annotations(line_idx));
+                annotations.extend(self.selection_annotation(line_idx));
+                annotations.extend(self.bracket_annotations(line_idx));
+                annotations.extend(self.extra_caret_annotations(line_idx));
+                for (row_in_line, columns) in self.visual_rows(line).into_iter().enumerate() {
+                    if current_row >= height {
+                        break;
+                    }
+                    let mut row = if row_in_line == 0 {
+                        self.build_gutter(line_idx)
+                    } else {
+                        self.blank_gutter()
+                    };
+                    row.append(line.annotated_visible_substr(
+                        columns.clone(),
+                        &annotations,
+                        self.options.tab_width,
+                    ));
+                    self.pad_past_line_end(&mut row, line_idx, line, &columns);
+                    self.render_row(current_row, row);
+                    current_row = current_row.saturating_add(1);
+                }
             } else {
-                Self::render_line(current_row, "~");
+                let mut row = self.blank_gutter();
+                if current_row == vertical_center && self.buffer.is_empty() {
+                    row.push(&Self::build_welcome_message(width), None);
+                } else {
+                    row.push("~", None);
+                }
+                self.render_row(current_row, row);
+                current_row = current_row.saturating_add(1);
             }
+            line_idx = line_idx.saturating_add(1);
+        }
+        self.viewport.dirty_rows.fill(false);
+    }
+
+    /// The summary row of a fold: how many lines it holds and how the first
+    /// one starts.
+    fn build_fold_row(&self, fold: FoldRange, first_line: &Line) -> AnnotatedString {
+        let mut row = self.build_gutter(fold.start);
+        let summary = format!(
+            "+--{:>3} lines: {}",
+            fold.end.saturating_sub(fold.start).saturating_add(1),
+            first_line.as_str().trim().replace('\t', " ")
+        );
+        let mut width_left = self.text_width();
+        let visible: String = summary
+            .chars()
+            .take_while(|&character| {
+                let width = character.width().unwrap_or(0);
+                let fits = width <= width_left;
+                width_left = width_left.saturating_sub(width);
+                fits
+            })
+            .collect();
+        row.push(&visible, Some(AnnotationType::Fold));
+        row
+    }
+
+    fn build_gutter(&self, line_idx: usize) -> AnnotatedString {
+        let mut gutter = AnnotatedString::default();
+        let gutter_width = self.gutter_width();
+        if gutter_width == 0 {
+            return gutter;
         }
-        self.needs_redraw = false;
+        let number_width = gutter_width.saturating_sub(1);
+        if line_idx < self.buffer.line_count() {
+            let line_number = line_idx.saturating_add(1);
+            let text = if self.line_numbers == LineNumbers::Absolute {
+                format!("{line_number:>number_width$} ")
+            } else if line_idx == self.viewport.caret.y {
+                // Like vim's `number relativenumber`, the caret line keeps its
+                // absolute number, left-aligned.
+                format!("{line_number:<number_width$} ")
+            } else {
+                let distance = line_idx.abs_diff(self.viewport.caret.y);
+                format!("{distance:>number_width$} ")
+            };
+            gutter.push(&text, Some(AnnotationType::LineNumber));
+        } else {
+            gutter = self.blank_gutter();
+        }
+        gutter
+    }
+
+    fn blank_gutter(&self) -> AnnotatedString {
+        let mut gutter = AnnotatedString::default();
+        gutter.push(&" ".repeat(self.gutter_width()), None);
+        gutter
     }
 
     fn build_welcome_message(width: usize) -> String {
@@ -66,21 +1582,830 @@ impl View {
             return " ".to_string();
         }
         let welcome_message = format!("{NAME} editor -- version {VERSION}");
-        let len = welcome_message.len();
-        if width <= len {
-            return "~".to_string();
+        let len = welcome_message.width();
+        let remaining_width = width.saturating_sub(1);
+        if remaining_width <= len {
+            // Too narrow to center, so show as much as fits right after the tilde.
+            return format!("~{welcome_message}").chars().take(width).collect();
+        }
+
+        #[allow(clippy::integer_division)]
+        let padding = remaining_width.saturating_sub(len) / 2;
+
+        format!("~{}{}", " ".repeat(padding), welcome_message)
+    }
+
+    pub fn find(
+        &self,
+        query: &Query,
+        from: Location,
+        direction: SearchDirection,
+    ) -> Option<Location> {
+        self.buffer.find(query, from, direction)
+    }
+
+    pub fn find_wrapping(
+        &self,
+        query: &Query,
+        from: Location,
+        direction: SearchDirection,
+    ) -> Option<(Location, bool)> {
+        self.buffer.find_wrapping(query, from, direction)
+    }
+
+    pub fn find_regex(
+        &self,
+        re: &Regex,
+        from: Location,
+        direction: SearchDirection,
+    ) -> Option<RegexMatch> {
+        self.buffer.find_regex(re, from, direction)
+    }
+
+    /// Replaces a regex match, expanding `$1` and so on in `template`, and
+    /// returns the location right after the replacement.
+    pub fn replace_regex_match(&mut self, found: &RegexMatch, template: &str) -> Location {
+        self.set_caret(found.start());
+        let end = self.edit_with_folds(|view| {
+            let end = view.buffer.replace_regex_match(found, template);
+            view.set_caret(end);
+            end
+        });
+        self.mark_all_dirty();
+        end
+    }
+
+    pub fn count_matches(&self, query: &Query, at: Location) -> (usize, usize) {
+        self.buffer.count_matches(query, at)
+    }
+
+    /// Replaces the match of `query` at `at` and returns the location right
+    /// after the replacement.
+    pub fn replace_match(&mut self, at: Location, query: &Query, with: &str) -> Location {
+        let len = self.buffer.line(at.y).map_or(0, |line| {
+            line.find_all(query)
+                .into_iter()
+                .find(|range| range.start == at.x)
+                .map_or(0, |range| range.len())
+        });
+        self.set_caret(at);
+        let end = self.edit_with_folds(|view| {
+            let end = view.buffer.replace_range(at, len, with);
+            view.set_caret(end);
+            end
+        });
+        self.mark_all_dirty();
+        end
+    }
+
+    pub fn insert_char(&mut self, character: char) {
+        self.edit_at_carets(|view| view.insert_char_at_caret(character));
+    }
+
+    fn insert_char_at_caret(&mut self, character: char) {
+        self.cut_selection();
+        if self.options.auto_pairs && self.insert_paired(character) {
+            return;
+        }
+        let line_count = self.buffer.line_count();
+        let end = self.buffer.insert_char(character, self.viewport.caret);
+        self.set_caret(end);
+        self.mark_edit_dirty(end.y, line_count);
+    }
+
+    fn char_at(&self, at: Location) -> Option<char> {
+        let line = self.buffer.line(at.y)?;
+        let byte_idx = line.grapheme_idx_to_byte_idx(at.x);
+        line.as_str().get(byte_idx..)?.chars().next()
+    }
+
+    /// Types over a closer that `auto_pairs` put in, or types an opener
+    /// along with its closer, leaving the caret between them. Quotes only
+    /// pair up outside of words, and nothing pairs up right before other
+    /// text. Returns whether `character` was handled.
+    fn insert_paired(&mut self, character: char) -> bool {
+        let caret = self.viewport.caret;
+        let next = self.char_at(caret);
+        if next == Some(character) && self.buffer.is_auto_closer(caret) {
+            self.buffer.forget_auto_closer(caret);
+            self.set_caret(Location {
+                x: caret.x.saturating_add(1),
+                y: caret.y,
+            });
+            return true;
+        }
+        let Some(&(_, closer)) = PAIRS.iter().find(|&&(opener, _)| opener == character) else {
+            return false;
+        };
+        let previous = caret
+            .x
+            .checked_sub(1)
+            .and_then(|x| self.char_at(Location { x, y: caret.y }));
+        let fits_after = closer != character || previous.is_none_or(|c| !c.is_alphanumeric());
+        let fits_before = next.is_none_or(|c| c.is_whitespace() || ")]}".contains(c));
+        if !fits_after || !fits_before {
+            return false;
+        }
+        self.buffer
+            .insert_str(&format!("{character}{closer}"), caret);
+        let inside = Location {
+            x: caret.x.saturating_add(1),
+            y: caret.y,
+        };
+        self.set_caret(inside);
+        self.buffer.add_auto_closer(inside);
+        self.mark_line_dirty(caret.y);
+        true
+    }
+
+    /// Deletes an opener together with the closer `auto_pairs` put right
+    /// after it. Returns whether the caret was between such a pair.
+    fn backspace_pair(&mut self) -> bool {
+        let caret = self.viewport.caret;
+        let Some(x) = caret.x.checked_sub(1) else {
+            return false;
+        };
+        let start = Location { x, y: caret.y };
+        let (Some(opener), Some(closer)) = (self.char_at(start), self.char_at(caret)) else {
+            return false;
+        };
+        if !PAIRS.contains(&(opener, closer)) || !self.buffer.is_auto_closer(caret) {
+            return false;
+        }
+        self.buffer.forget_auto_closer(caret);
+        let end = Location {
+            x: caret.x.saturating_add(1),
+            y: caret.y,
+        };
+        self.buffer.delete_range(start, end);
+        self.set_caret(start);
+        self.mark_line_dirty(caret.y);
+        true
+    }
+
+    /// Edits that keep the number of lines only touch the caret's line;
+    /// anything else shifts the rows below it.
+    fn mark_edit_dirty(&mut self, line_idx: usize, line_count_before: usize) {
+        if self.buffer.line_count() == line_count_before {
+            self.mark_line_dirty(line_idx);
+        } else {
+            self.mark_all_dirty();
+        }
+    }
+
+    /// Types `text` again as one undo step, as if its keys were pressed.
+    pub fn retype(&mut self, text: &str) {
+        let checkpoint = self.buffer.undo_checkpoint();
+        for character in text.chars() {
+            match character {
+                '\n' => self.insert_newline(),
+                '\t' => self.insert_tab(),
+                character => self.insert_char(character),
+            }
+        }
+        self.buffer.merge_undo_steps(checkpoint);
+    }
+
+    pub fn insert_tab(&mut self) {
+        if self.options.expand_tab {
+            self.paste(&" ".repeat(self.options.tab_width));
+        } else {
+            self.insert_char('\t');
+        }
+    }
+
+    pub fn insert_newline(&mut self) {
+        self.edit_at_carets(Self::insert_newline_at_caret);
+    }
+
+    fn insert_newline_at_caret(&mut self) {
+        self.cut_selection();
+        let indent_unit = if self.options.expand_tab {
+            " ".repeat(self.options.tab_width)
+        } else {
+            String::from("\t")
+        };
+        let end = self
+            .buffer
+            .insert_newline(self.viewport.caret, &indent_unit);
+        self.set_caret(end);
+        self.mark_all_dirty();
+    }
+
+    pub fn delete(&mut self) {
+        self.edit_at_carets(Self::delete_at_caret);
+    }
+
+    /// Deletes `count` characters from the caret on as one undo step,
+    /// without going past the end of the line unless it is already there.
+    pub fn delete_chars(&mut self, count: usize) {
+        let caret = self.viewport.caret;
+        let left_on_line = self.buffer.line_len(caret.y).saturating_sub(caret.x);
+        let count = if left_on_line == 0 {
+            1
+        } else {
+            min(count, left_on_line)
+        };
+        let checkpoint = self.buffer.undo_checkpoint();
+        for _ in 0..count {
+            self.delete();
+        }
+        self.buffer.merge_undo_steps(checkpoint);
+    }
+
+    fn delete_at_caret(&mut self) {
+        if self.cut_selection().is_some() {
+            return;
+        }
+        let line_count = self.buffer.line_count();
+        self.buffer.delete(self.viewport.caret);
+        self.mark_edit_dirty(self.viewport.caret.y, line_count);
+    }
+
+    pub fn backspace(&mut self) {
+        self.edit_at_carets(Self::backspace_at_caret);
+    }
+
+    fn backspace_at_caret(&mut self) {
+        if self.cut_selection().is_some() {
+            return;
+        }
+        if self.options.auto_pairs && self.backspace_pair() {
+            return;
+        }
+        if self.options.smart_backspace && self.backspace_to_tab_stop() {
+            return;
+        }
+        let line_count = self.buffer.line_count();
+        if let Some(previous) = self.buffer.backspace(self.viewport.caret) {
+            self.set_caret(previous);
+        }
+        self.mark_edit_dirty(self.viewport.caret.y, line_count);
+    }
+
+    /// Removes the run of spaces between the caret and the previous tab
+    /// stop, if there is one.
+    fn backspace_to_tab_stop(&mut self) -> bool {
+        let column = self.text_location_to_position(self.viewport.caret).col;
+        let tab_width = self.options.tab_width.max(1);
+        let distance = match column.checked_rem(tab_width) {
+            Some(0) | None => tab_width,
+            Some(remainder) => remainder,
+        };
+        let start = Location {
+            x: self.viewport.caret.x.saturating_sub(distance),
+            y: self.viewport.caret.y,
+        };
+        let preceding = self.buffer.text_range(start, self.viewport.caret);
+        if preceding.chars().count() < 2 || preceding.chars().any(|character| character != ' ') {
+            return false;
+        }
+        self.buffer.delete_range(start, self.viewport.caret);
+        self.set_caret(start);
+        self.mark_line_dirty(start.y);
+        true
+    }
+
+    /// Changes the case of the selection, which stays selected, or else of
+    /// the word under the caret.
+    pub fn transform_case(&mut self, kind: CaseTransform) {
+        if let Some((start, end)) = self.selection() {
+            self.clear_folds();
+            let end = self.buffer.transform_range(start, end, kind);
+            self.selection_anchor = Some(start);
+            self.set_caret(end);
+        } else if let Some((start, end)) = self.buffer.word_at(self.viewport.caret) {
+            self.edit_with_folds(|view| {
+                view.buffer.transform_range(start, end, kind);
+                let caret = view.buffer.clamp_location(view.viewport.caret);
+                view.set_caret(caret);
+            });
+        }
+        self.mark_all_dirty();
+    }
+
+    /// Toggles line comments on the selected lines, or the caret's line.
+    /// Returns `false` if the file type has no line comments.
+    pub fn toggle_comment(&mut self) -> bool {
+        let Some(prefix) = self.buffer.file_type.line_comment() else {
+            return false;
+        };
+        let caret = self.viewport.caret;
+        let (start, end) = self.selection().unwrap_or((caret, caret));
+        // A selection ending at the start of a line doesn't take that line in.
+        let last_row = if end.y > start.y && end.x == 0 {
+            end.y.saturating_sub(1)
+        } else {
+            end.y
+        };
+        let before = |at: Location| (at, self.buffer.line_len(at.y).saturating_sub(at.x));
+        let caret_before = before(caret);
+        let anchor_before = self.selection_anchor.map(before);
+        self.clear_folds();
+        self.buffer.toggle_comment(start.y..=last_row, prefix);
+        // Carets past the indentation stay on the same text.
+        let after = |(at, from_end): (Location, usize)| {
+            let first_non_blank = self.buffer.first_non_blank(at.y);
+            if at.x < first_non_blank {
+                return at;
+            }
+            let x = self.buffer.line_len(at.y).saturating_sub(from_end);
+            Location {
+                x: x.max(first_non_blank),
+                y: at.y,
+            }
+        };
+        self.selection_anchor = anchor_before.map(after);
+        let caret = after(caret_before);
+        self.set_caret(caret);
+        self.mark_all_dirty();
+        true
+    }
+
+    pub fn duplicate_line(&mut self) {
+        self.clear_folds();
+        let caret = self.viewport.caret;
+        if self.buffer.duplicate_line(caret.y) {
+            self.set_caret(Location {
+                x: caret.x,
+                y: caret.y.saturating_add(1),
+            });
+            self.mark_all_dirty();
+        }
+    }
+
+    /// Joins the caret's line with the next one, or all the selected lines
+    /// into one, leaving the caret where the last two were joined.
+    pub fn join_lines(&mut self) {
+        let caret = self.viewport.caret;
+        let (start, end) = self.selection().unwrap_or((caret, caret));
+        // As with comments, a selection ending at the start of a line doesn't
+        // take that line in.
+        let last_row = if end.y > start.y.saturating_add(1) && end.x == 0 {
+            end.y.saturating_sub(1)
+        } else {
+            end.y
+        };
+        let joins = last_row.saturating_sub(start.y).max(1);
+        self.clear_folds();
+        self.selection_anchor = None;
+        let checkpoint = self.buffer.undo_checkpoint();
+        let mut join_point = None;
+        for _ in 0..joins {
+            match self.buffer.join_lines(start.y) {
+                Some(at) => join_point = Some(at),
+                None => break,
+            }
+        }
+        self.buffer.merge_undo_steps(checkpoint);
+        if let Some(at) = join_point {
+            self.set_caret(at);
+            self.mark_all_dirty();
+        }
+    }
+
+    /// Moves the caret's line past its neighbor, keeping the caret on it.
+    pub fn move_line(&mut self, direction: SearchDirection) {
+        self.clear_folds();
+        let caret = self.viewport.caret;
+        if self.buffer.move_line(caret.y, direction) {
+            let y = match direction {
+                SearchDirection::Forward => caret.y.saturating_add(1),
+                SearchDirection::Backward => caret.y.saturating_sub(1),
+            };
+            self.set_caret(Location { x: caret.x, y });
+            self.mark_all_dirty();
+        }
+    }
+
+    pub fn cut_line(&mut self) -> Option<String> {
+        self.clear_folds();
+        let line = self.buffer.remove_line(self.viewport.caret.y)?;
+        self.set_caret(Location {
+            x: 0,
+            y: min(self.viewport.caret.y, self.buffer.line_count()),
+        });
+        self.mark_all_dirty();
+        Some(line)
+    }
+
+    /// Cuts `count` lines from the caret's down as one undo step, returning
+    /// them all.
+    pub fn cut_lines(&mut self, count: usize) -> Option<String> {
+        let checkpoint = self.buffer.undo_checkpoint();
+        let mut text: Option<String> = None;
+        for _ in 0..count {
+            let Some(line) = self.cut_line() else {
+                break;
+            };
+            text.get_or_insert_default().push_str(&line);
+        }
+        self.buffer.merge_undo_steps(checkpoint);
+        text
+    }
+
+    /// Deletes the rest of the caret's line, or the line break at its end,
+    /// and returns what was deleted.
+    pub fn kill_to_line_end(&mut self) -> Option<String> {
+        self.clear_folds();
+        let line_count = self.buffer.line_count();
+        let text = self.buffer.truncate_line(self.viewport.caret);
+        self.mark_edit_dirty(self.viewport.caret.y, line_count);
+        (!text.is_empty()).then_some(text)
+    }
+
+    pub fn kill_to_line_start(&mut self) -> Option<String> {
+        let text = self.edit_with_folds(|view| {
+            let text = view.buffer.truncate_line_head(view.viewport.caret);
+            view.set_caret(Location {
+                x: 0,
+                y: view.viewport.caret.y,
+            });
+            text
+        });
+        self.mark_line_dirty(self.viewport.caret.y);
+        (!text.is_empty()).then_some(text)
+    }
+
+    pub fn paste(&mut self, text: &str) {
+        self.edit_at_carets(|view| view.paste_at_caret(text));
+    }
+
+    fn paste_at_caret(&mut self, text: &str) {
+        self.cut_selection();
+        let end = self.buffer.insert_str(text, self.viewport.caret);
+        self.set_caret(end);
+        self.mark_all_dirty();
+    }
+
+    /// Drops everything tied to places in the text, for changes that may
+    /// have moved any of it.
+    fn forget_text_positions(&mut self) {
+        self.selection_anchor = None;
+        self.buffer.forget_auto_closers();
+        self.folds.clear();
+    }
+
+    pub fn undo(&mut self) {
+        self.forget_text_positions();
+        if let Some(location) = self.buffer.undo() {
+            self.set_caret(location);
+        }
+        self.mark_all_dirty();
+    }
+
+    pub fn redo(&mut self) {
+        self.forget_text_positions();
+        if let Some(location) = self.buffer.redo() {
+            self.set_caret(location);
+        }
+        self.mark_all_dirty();
+    }
+
+    /// Goes back or forward in time through the undo history, across
+    /// branches.
+    pub fn travel_history(&mut self, later: bool) {
+        self.forget_text_positions();
+        if let Some(location) = self.buffer.travel(later) {
+            self.set_caret(location);
+        }
+        self.mark_all_dirty();
+    }
+
+    pub fn undo_list(&self) -> UndoList {
+        self.buffer.undo_list()
+    }
+
+    pub fn changed_on_disk(&self) -> bool {
+        self.buffer.changed_on_disk()
+    }
+
+    /// Throws away all edits and shows the file as it is on disk now.
+    pub fn reload(&mut self) -> Result<(), Error> {
+        self.buffer.reload()?;
+        self.line_states.clear();
+        self.forget_text_positions();
+        let caret = self.buffer.clamp_location(self.viewport.caret);
+        self.set_caret(caret);
+        self.mark_all_dirty();
+        Ok(())
+    }
+
+    pub fn save(&mut self) -> Result<(), Error> {
+        let result = self.buffer.save(self.options, self.viewport.caret);
+        self.after_tidy_up();
+        result
+    }
+
+    /// Keeps the caret on text that saving may have trimmed away.
+    fn after_tidy_up(&mut self) {
+        let line_count = self.buffer.line_count();
+        self.folds.retain(|fold| fold.end < line_count);
+        let caret = self.buffer.clamp_location(self.viewport.caret);
+        self.set_caret(caret);
+        self.mark_all_dirty();
+    }
+
+    pub fn save_as(&mut self, file_name: &str) -> Result<(), Error> {
+        self.buffer.file_name = Some(file_name.to_string());
+        self.buffer.file_type = FileType::from_file_name(file_name);
+        self.highlighter = Highlighter::for_file_type(self.buffer.file_type);
+        self.line_states.clear();
+        let result = self.buffer.save(self.options, self.viewport.caret);
+        self.after_tidy_up();
+        result
+    }
+
+    pub fn stats(&self) -> BufferStats {
+        self.buffer.stats()
+    }
+
+    pub fn file_name(&self) -> Option<&str> {
+        self.buffer.file_name.as_deref()
+    }
+
+    pub const fn has_file_name(&self) -> bool {
+        self.buffer.file_name.is_some()
+    }
+
+    /// Whether edits are rejected, either by choice or because the file is
+    /// only partly loaded.
+    pub const fn is_read_only(&self) -> bool {
+        self.buffer.read_only || self.buffer.is_lazy()
+    }
+
+    pub const fn set_read_only(&mut self, read_only: bool) {
+        self.buffer.read_only = read_only;
+    }
+
+    pub const fn toggle_read_only(&mut self) {
+        self.buffer.read_only = !self.buffer.read_only;
+    }
+
+    pub const fn is_lazy(&self) -> bool {
+        self.buffer.is_lazy()
+    }
+
+    /// Replaces a lazily loaded buffer with a fully loaded one, keeping the
+    /// caret and scroll position. Folds are dropped along with the marks.
+    pub fn load_fully(&mut self) -> Result<(), Error> {
+        self.buffer.load_fully()?;
+        self.line_states.clear();
+        self.forget_text_positions();
+        self.mark_all_dirty();
+        Ok(())
+    }
+
+    pub const fn is_dirty(&self) -> bool {
+        self.buffer.is_dirty()
+    }
+
+    pub fn document_status(&self) -> DocumentStatus {
+        DocumentStatus {
+            file_name: self.buffer.file_name.clone(),
+            file_type: self.buffer.file_type,
+            line_count: self.buffer.line_count(),
+            is_modified: self.buffer.is_dirty(),
+            is_read_only: self.is_read_only(),
+            caret: self.viewport.caret,
+            ..DocumentStatus::default()
         }
+    }
 
-        let padding = (width.saturating_sub(len).saturating_sub(1)) / 2;
+    /// Opens `file_name`, or starts an empty buffer under that name if it
+    /// doesn't exist yet so the first save creates it.
+    pub fn load(&mut self, file_name: &str) -> Result<(), Error> {
+        let is_large = metadata(file_name).is_ok_and(|metadata| metadata.len() > LAZY_LOAD_SIZE);
+        let buffer = if is_large {
+            Buffer::load_lazy(file_name)
+        } else {
+            Buffer::load(file_name)
+        };
+        let buffer = match buffer {
+            Ok(buffer) => buffer,
+            Err(err) if err.kind() == ErrorKind::NotFound => Buffer::with_file_name(file_name),
+            Err(err) => return Err(err),
+        };
+        self.highlighter = Highlighter::for_file_type(buffer.file_type);
+        self.line_states.clear();
+        self.buffer = buffer;
+        self.forget_text_positions();
+        self.mark_all_dirty();
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::Location;
+    use super::super::command::Move;
+    use super::super::filetype::FileType;
+    use super::super::terminal::{Recorder, Size, Terminal};
+    use super::{Buffer, CaseTransform, FoldRange, Highlighter, Line, Options, Query, View};
+    use core::cell::RefCell;
+    use std::rc::Rc;
+    use unicode_width::UnicodeWidthStr;
+
+    fn rust_view(text: &str) -> View {
+        let mut buffer = Buffer::from_string(text);
+        buffer.file_type = FileType::Rust;
+        View {
+            buffer,
+            highlighter: Highlighter::for_file_type(FileType::Rust),
+            ..View::default()
+        }
+    }
 
-        let mut full_message = format!("~{}{}", " ".repeat(padding), welcome_message);
-        full_message.truncate(width);
-        full_message
+    #[test]
+    fn block_comment_state_follows_edits_above() {
+        let mut view = rust_view("/* open\nstill\ncomment */\ncode\n");
+        assert_eq!(view.block_comment_depth_at(1), 1);
+        assert_eq!(view.block_comment_depth_at(3), 0);
+        view.buffer.replace_range(Location { x: 0, y: 0 }, 2, "//");
+        assert_eq!(view.block_comment_depth_at(1), 0);
+        view.buffer.replace_range(Location { x: 0, y: 3 }, 0, "/*");
+        assert_eq!(view.block_comment_depth_at(2), 0);
+        assert_eq!(view.block_comment_depth_at(3), 0);
+        assert_eq!(view.block_comment_depth_at(4), 1);
     }
 
-    pub fn load(&mut self, file_name: &str) {
-        if let Ok(buffer) = Buffer::load(file_name) {
-            self.buffer = buffer;
+    #[test]
+    fn welcome_message_fits_narrow_screens() {
+        for width in [5, 20] {
+            let message = View::build_welcome_message(width);
+            assert!(message.starts_with('~'));
+            assert!(message.width() <= width);
+            assert!(!message.contains("  "));
         }
     }
+
+    #[test]
+    fn welcome_message_is_centered() {
+        let message = View::build_welcome_message(80);
+        assert!(message.starts_with('~'));
+        assert!(message.width() <= 80);
+        let text = message.trim_start_matches('~').trim_start();
+        let left = message.width().saturating_sub(text.width());
+        let right = 80_usize.saturating_sub(message.width());
+        assert!(
+            left.abs_diff(right) <= 1,
+            "{left} columns left, {right} right"
+        );
+    }
+
+    #[test]
+    fn full_load_keeps_read_only_and_drops_folds_and_marks() {
+        let path =
+            std::env::temp_dir().join(format!("rustamundo-{}-full-load", std::process::id()));
+        std::fs::write(&path, "one\ntwo\nthree\n").unwrap();
+        let mut view = View {
+            buffer: Buffer::load_lazy(&path.display().to_string()).unwrap(),
+            folds: vec![FoldRange { start: 0, end: 1 }],
+            ..View::default()
+        };
+        view.set_read_only(true);
+        view.set_mark('a');
+
+        view.load_fully().unwrap();
+        assert!(!view.is_lazy());
+        assert!(view.is_read_only());
+        assert!(view.folds.is_empty());
+        assert_eq!(view.buffer.mark('a'), None);
+        assert_eq!(view.buffer.line_count(), 3);
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    fn home_columns(text: &str, from_x: usize) -> [usize; 2] {
+        let mut view = View {
+            buffer: Buffer::from_string(text),
+            ..View::default()
+        };
+        view.set_caret(Location { x: from_x, y: 0 });
+        view.move_caret(Move::StartOfText);
+        let first = view.viewport.caret.x;
+        view.move_caret(Move::StartOfText);
+        [first, view.viewport.caret.x]
+    }
+
+    #[test]
+    fn home_toggles_between_indentation_and_column_zero() {
+        assert_eq!(home_columns("    let x;", 7), [4, 0]);
+        assert_eq!(home_columns("    let x;", 0), [4, 0]);
+        assert_eq!(home_columns("\tlet x;", 3), [1, 0]);
+    }
+
+    #[test]
+    fn home_on_unindented_line_goes_to_column_zero() {
+        assert_eq!(home_columns("let x;", 4), [0, 0]);
+        assert_eq!(home_columns("", 0), [0, 0]);
+    }
+
+    #[test]
+    fn home_on_whitespace_only_line_toggles_with_its_end() {
+        assert_eq!(home_columns("   ", 1), [3, 0]);
+        assert_eq!(home_columns("   ", 3), [0, 3]);
+    }
+
+    fn folded_view() -> View {
+        let mut view = rust_view("    fn a() {\n        x;\n    }\nb\n");
+        assert!(view.toggle_fold());
+        assert!(view.folds == [FoldRange { start: 0, end: 2 }]);
+        view
+    }
+
+    #[test]
+    fn edits_on_a_fold_unfold_it() {
+        let mut view = folded_view();
+        view.set_caret(Location { x: 4, y: 0 });
+        assert_eq!(view.kill_to_line_start().as_deref(), Some("    "));
+        assert!(view.folds.is_empty());
+
+        let mut view = folded_view();
+        assert!(view.toggle_comment());
+        assert!(view.folds.is_empty());
+
+        let mut view = folded_view();
+        view.set_caret(Location { x: 4, y: 0 });
+        view.transform_case(CaseTransform::Upper);
+        assert!(view.folds.is_empty());
+        assert_eq!(view.buffer.line(0).map(Line::as_str), Some("    FN a() {"));
+    }
+
+    #[test]
+    fn replacing_a_hidden_match_unfolds_it() {
+        let mut view = folded_view();
+        let query = Query::parse("x").unwrap();
+        let end = view.replace_match(Location { x: 8, y: 1 }, &query, "y\nz");
+        assert_eq!(end, Location { x: 1, y: 2 });
+        assert!(view.folds.is_empty());
+        assert_eq!(view.buffer.line_count(), 5);
+    }
+
+    const SCREEN: Size = Size {
+        height: 10,
+        width: 20,
+    };
+
+    /// A view of the lines "1" to "50" keeping `scroll_off` lines around the
+    /// caret, drawn into a recorder.
+    fn numbered_view(scroll_off: usize) -> (View, Rc<RefCell<Vec<u8>>>) {
+        let output = Rc::new(RefCell::new(Vec::new()));
+        Terminal::set_target(Box::new(Recorder {
+            output: Rc::clone(&output),
+            events: Vec::new().into(),
+            size: SCREEN,
+        }));
+        let lines: Vec<String> = (1..=50).map(|number| number.to_string()).collect();
+        let text = lines.join("\n");
+        let view = View {
+            buffer: Buffer::from_string(&text),
+            options: Options {
+                scroll_off,
+                ..Options::default()
+            },
+            ..View::default()
+        };
+        (view, output)
+    }
+
+    /// The first line on screen and the screen row the caret is on, after
+    /// scrolling the caret into view and drawing.
+    fn shown(view: &mut View, output: &RefCell<Vec<u8>>) -> (String, usize) {
+        view.scroll_into_view();
+        view.render();
+        Terminal::execute().unwrap();
+        let screen = Recorder::screen(&output.borrow(), SCREEN);
+        (screen[0].clone(), view.caret_position().row)
+    }
+
+    #[test]
+    fn scroll_off_keeps_lines_around_jumps() {
+        let (mut view, output) = numbered_view(3);
+        view.go_to_line(20);
+        assert_eq!(shown(&mut view, &output), ("14".to_string(), 6));
+        view.go_to_line(18);
+        assert_eq!(shown(&mut view, &output), ("14".to_string(), 4));
+        view.go_to_line(17);
+        assert_eq!(shown(&mut view, &output), ("14".to_string(), 3));
+        view.go_to_line(16);
+        assert_eq!(shown(&mut view, &output), ("13".to_string(), 3));
+        // The margin shrinks at either end of the document.
+        view.go_to_line(50);
+        assert_eq!(shown(&mut view, &output), ("41".to_string(), 9));
+        view.go_to_line(1);
+        assert_eq!(shown(&mut view, &output), ("1".to_string(), 0));
+    }
+
+    #[test]
+    fn scroll_off_keeps_lines_around_page_moves() {
+        let (mut view, output) = numbered_view(3);
+        view.move_caret(Move::PageDown);
+        assert_eq!(shown(&mut view, &output), ("8".to_string(), 3));
+        view.move_caret(Move::PageDown);
+        assert_eq!(shown(&mut view, &output), ("18".to_string(), 3));
+        view.move_caret(Move::PageUp);
+        assert_eq!(shown(&mut view, &output), ("8".to_string(), 3));
+        view.move_caret(Move::PageUp);
+        assert_eq!(shown(&mut view, &output), ("1".to_string(), 0));
+    }
 }
@@ -1,21 +1,1556 @@
-use std::fs::read_to_string;
-use std::io::Error;
+use super::super::Location;
+use super::super::filetype::FileType;
+use super::Options;
+use super::line::Line;
+use super::query::Query;
+use super::undostack::{Edit, UndoList, UndoStack};
+use core::cmp::min;
+use core::fmt::{self, Display};
+use core::ops::{Range, RangeInclusive};
+use lazy::LazySource;
+use marks::Marks;
+use regex::Regex;
+use std::fs::{
+    File, canonicalize, copy, metadata, read_to_string, remove_file, rename, set_permissions,
+};
+use std::io::{Error, Write};
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+use unicode_segmentation::UnicodeSegmentation;
+
+mod lazy;
+mod marks;
+
+/// How many lines to keep in memory on either side of the requested rows of
+/// a lazily loaded file.
+const LAZY_MARGIN: usize = 1000;
+const SEARCH_CHUNK: usize = 4096;
+
+#[derive(Default, Clone, Copy, PartialEq, Eq)]
+pub enum LineEnding {
+    #[default]
+    Lf,
+    Crlf,
+}
+
+impl LineEnding {
+    pub const fn as_str(self) -> &'static str {
+        match self {
+            Self::Lf => "\n",
+            Self::Crlf => "\r\n",
+        }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum SearchDirection {
+    Forward,
+    Backward,
+}
+
+/// A regex match over the graphemes `range` of line `row`. `groups` holds
+/// the range of each capture group that took part, the whole match first.
+pub struct RegexMatch {
+    pub row: usize,
+    pub range: Range<usize>,
+    pub groups: Vec<Option<Range<usize>>>,
+}
+
+impl RegexMatch {
+    pub const fn start(&self) -> Location {
+        Location {
+            x: self.range.start,
+            y: self.row,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Default)]
+pub struct BufferStats {
+    pub lines: usize,
+    pub words: usize,
+    pub characters: usize,
+    pub bytes: usize,
+}
+
+#[derive(Clone, Copy)]
+pub enum CaseTransform {
+    Upper,
+    Lower,
+    Title,
+}
+
+impl CaseTransform {
+    fn apply(self, text: &str) -> String {
+        match self {
+            Self::Upper => text.to_uppercase(),
+            Self::Lower => text.to_lowercase(),
+            Self::Title => text
+                .split_word_bounds()
+                .map(|word| {
+                    let mut chars = word.chars();
+                    chars.next().map_or_else(String::new, |first| {
+                        first
+                            .to_uppercase()
+                            .chain(chars.as_str().to_lowercase().chars())
+                            .collect()
+                    })
+                })
+                .collect(),
+        }
+    }
+}
 
 #[derive(Default)]
 pub struct Buffer {
-    pub lines: Vec<String>,
+    lines: Vec<Line>,
+    /// The row `lines[0]` holds, which is only nonzero for lazily loaded
+    /// files where `lines` is a window into the file.
+    first_resident_row: usize,
+    lazy: Option<LazySource>,
+    pub file_name: Option<String>,
+    pub file_type: FileType,
+    pub line_ending: LineEnding,
+    /// Whether the text ended with a line ending, which `save` keeps.
+    pub final_line_ending: bool,
+    dirty: bool,
+    pub read_only: bool,
+    history: UndoStack,
+    marks: Marks,
+    /// When the file was last modified as of loading or saving it.
+    disk_modified: Option<SystemTime>,
+    /// The first row edited since `take_first_changed_row` last asked.
+    first_changed_row: Option<usize>,
 }
 
 impl Buffer {
     pub fn load(file_name: &str) -> Result<Self, Error> {
         let contents = read_to_string(file_name)?;
+        Ok(Self {
+            file_name: Some(file_name.to_string()),
+            file_type: FileType::from_file_name(file_name),
+            disk_modified: modified_time(file_name),
+            ..Self::from_string(&contents)
+        })
+    }
+
+    /// Discards all edits and reads the file again, keeping it read-only if
+    /// it was.
+    pub fn reload(&mut self) -> Result<(), Error> {
+        let Some(file_name) = self.file_name.clone() else {
+            return Ok(());
+        };
+        let reloaded = if self.is_lazy() {
+            Self::load_lazy(&file_name)?
+        } else {
+            Self::load(&file_name)?
+        };
+        *self = Self {
+            read_only: self.read_only,
+            ..reloaded
+        };
+        Ok(())
+    }
+
+    /// Reads all of a lazily loaded file into memory, keeping it read-only
+    /// if it was. Like any freshly opened file, it starts with no marks and
+    /// no undo history.
+    pub fn load_fully(&mut self) -> Result<(), Error> {
+        let Some(file_name) = self.file_name.clone() else {
+            return Ok(());
+        };
+        *self = Self {
+            read_only: self.read_only,
+            marks: Marks::default(),
+            ..Self::load(&file_name)?
+        };
+        Ok(())
+    }
+
+    /// Whether another program changed the file since it was loaded or last
+    /// saved. A file that has since been deleted does not count as changed.
+    pub fn changed_on_disk(&self) -> bool {
+        let now = self.file_name.as_deref().and_then(modified_time);
+        matches!((self.disk_modified, now), (Some(seen), Some(now)) if seen != now)
+    }
+
+    pub fn with_file_name(file_name: &str) -> Self {
+        Self {
+            file_name: Some(file_name.to_string()),
+            file_type: FileType::from_file_name(file_name),
+            ..Self::default()
+        }
+    }
+
+    /// Opens `file_name` read-only, indexing its lines but reading only the
+    /// ones around what is being looked at.
+    pub fn load_lazy(file_name: &str) -> Result<Self, Error> {
+        Ok(Self {
+            file_name: Some(file_name.to_string()),
+            file_type: FileType::from_file_name(file_name),
+            lazy: Some(LazySource::scan(file_name)?),
+            ..Self::default()
+        })
+    }
+
+    pub const fn is_lazy(&self) -> bool {
+        self.lazy.is_some()
+    }
+
+    /// Reads the lines around `rows` into memory if they aren't already.
+    pub fn ensure_resident(&mut self, rows: Range<usize>) {
+        let Some(source) = &self.lazy else {
+            return;
+        };
+        let resident_end = self.first_resident_row.saturating_add(self.lines.len());
+        let rows_end = min(rows.end, source.line_count());
+        if !self.lines.is_empty()
+            && rows.start >= self.first_resident_row
+            && rows_end <= resident_end
+        {
+            return;
+        }
+        let start = rows.start.saturating_sub(LAZY_MARGIN);
+        let window = start..rows.end.saturating_add(LAZY_MARGIN);
+        if let Ok(lines) = source.read_lines(&window) {
+            self.lines = lines;
+            self.first_resident_row = start;
+        }
+    }
+
+    /// The first row edited since the last call, if any was.
+    pub const fn take_first_changed_row(&mut self) -> Option<usize> {
+        self.first_changed_row.take()
+    }
+
+    fn note_change(&mut self, row: usize) {
+        self.first_changed_row = Some(self.first_changed_row.map_or(row, |first| first.min(row)));
+    }
+
+    pub fn line(&self, row: usize) -> Option<&Line> {
+        self.lines.get(row.checked_sub(self.first_resident_row)?)
+    }
+
+    pub fn from_string(contents: &str) -> Self {
         let mut lines = Vec::new();
-        for value in contents.lines() {
-            lines.push(String::from(value));
+        for value in contents.split('\n') {
+            lines.push(Line::from(value.strip_suffix('\r').unwrap_or(value)));
+        }
+        if contents.is_empty() || contents.ends_with('\n') {
+            lines.pop();
         }
-        Ok(Self { lines })
+        // Mixed files are saved with whichever ending the majority of lines used.
+        let crlf_count = contents.matches("\r\n").count();
+        let lf_count = contents.matches('\n').count().saturating_sub(crlf_count);
+        let line_ending = if crlf_count > lf_count {
+            LineEnding::Crlf
+        } else {
+            LineEnding::Lf
+        };
+        Self {
+            lines,
+            first_resident_row: 0,
+            lazy: None,
+            file_name: None,
+            file_type: FileType::default(),
+            line_ending,
+            final_line_ending: contents.ends_with('\n'),
+            dirty: false,
+            read_only: false,
+            history: UndoStack::default(),
+            marks: Marks::default(),
+            disk_modified: None,
+            first_changed_row: None,
+        }
+    }
+
+    /// Writes the buffer to its file, tidied up as `options` ask. The tidying
+    /// only becomes an edit once the file is written, so a failed save leaves
+    /// the buffer as it was. With `options.backup`, the file's previous
+    /// contents are first copied to `<file>~`, and nothing is written if that
+    /// fails.
+    pub fn save(&mut self, options: Options, caret: Location) -> Result<(), Error> {
+        if let Some(source) = &self.lazy {
+            return Err(Error::other(format!(
+                "{} is only partly loaded",
+                source.file_name()
+            )));
+        }
+        if self.read_only {
+            return Err(Error::other("the buffer is read-only"));
+        }
+        let Some(file_name) = self.file_name.clone() else {
+            return Ok(());
+        };
+        if options.backup && Path::new(&file_name).exists() {
+            copy(&file_name, format!("{file_name}~")).map_err(|err| {
+                Error::new(err.kind(), format!("could not back up {file_name}: {err}"))
+            })?;
+        }
+        let tidies = options.trim_trailing_whitespace || options.ensure_final_newline;
+        let mut contents = if tidies {
+            self.tidied(options, caret).to_string()
+        } else {
+            self.to_string()
+        };
+        let wants_final_newline = options.ensure_final_newline || options.insert_final_newline;
+        if (wants_final_newline && !contents.is_empty())
+            || (self.final_line_ending && !self.lines.is_empty())
+        {
+            contents.push_str(self.line_ending.as_str());
+        }
+        write_atomically(Path::new(&file_name), &contents)?;
+        if tidies {
+            self.tidy_up(options, caret);
+        }
+        self.disk_modified = modified_time(&file_name);
+        self.dirty = false;
+        Ok(())
+    }
+
+    /// A copy of the text as `tidy_up` would leave it.
+    fn tidied(&self, options: Options, caret: Location) -> Self {
+        let mut copy = Self {
+            lines: self.lines.clone(),
+            line_ending: self.line_ending,
+            ..Self::default()
+        };
+        copy.tidy_up(options, caret);
+        copy
+    }
+
+    /// Strips trailing spaces and tabs from every line, and drops blank lines
+    /// at the end for `save` to end the file with one line ending, as one
+    /// undo step. On the caret's line only whitespace after the caret goes,
+    /// so the caret isn't left past the end of what it was typing.
+    fn tidy_up(&mut self, options: Options, caret: Location) {
+        let mut edits = Vec::new();
+        if options.trim_trailing_whitespace {
+            for y in 0..self.lines.len() {
+                let Some(line) = self.lines.get(y) else {
+                    break;
+                };
+                let text = line.as_str();
+                let Some(mut start) =
+                    line.byte_idx_to_grapheme_idx(text.trim_end_matches([' ', '\t']).len())
+                else {
+                    continue;
+                };
+                if y == caret.y {
+                    start = start.max(caret.x);
+                }
+                let end = line.grapheme_count();
+                if start < end {
+                    edits.push(self.apply_delete(Location { x: start, y }, Location { x: end, y }));
+                }
+            }
+        }
+        if options.ensure_final_newline {
+            let last_row = self.lines.len().saturating_sub(1);
+            let start = self
+                .lines
+                .iter()
+                .rposition(|line| !line.is_empty())
+                .map_or_else(Location::default, |y| Location {
+                    x: self.line_len(y),
+                    y,
+                });
+            if start.y < last_row {
+                let end = Location {
+                    x: self.line_len(last_row),
+                    y: last_row,
+                };
+                edits.push(self.apply_delete(start, end));
+            }
+        }
+        self.history.record(edits);
+    }
+
+    /// The text of each line in memory, which for a lazily loaded file is
+    /// only the part around what was last looked at.
+    pub fn iter_lines(&self) -> impl Iterator<Item = &str> {
+        self.lines.iter().map(Line::as_str)
     }
+
+    pub const fn is_dirty(&self) -> bool {
+        self.dirty
+    }
+
+    /// Counts the lines in memory. Characters are graphemes and, like the
+    /// bytes, are counted as `save` would write the text.
+    pub fn stats(&self) -> BufferStats {
+        let mut stats = BufferStats {
+            lines: self.lines.len(),
+            ..BufferStats::default()
+        };
+        for line in &self.lines {
+            stats.words = stats.words.saturating_add(line.word_count());
+            stats.characters = stats.characters.saturating_add(line.grapheme_count());
+            stats.bytes = stats.bytes.saturating_add(line.as_str().len());
+        }
+        let final_break = usize::from(self.final_line_ending && stats.lines > 0);
+        let line_breaks = stats.lines.saturating_sub(1).saturating_add(final_break);
+        stats.characters = stats.characters.saturating_add(line_breaks);
+        stats.bytes = stats
+            .bytes
+            .saturating_add(line_breaks.saturating_mul(self.line_ending.as_str().len()));
+        stats
+    }
+
     pub fn is_empty(&self) -> bool {
-        self.lines.is_empty()
+        self.line_count() == 0
+    }
+
+    pub fn line_count(&self) -> usize {
+        self.lazy
+            .as_ref()
+            .map_or(self.lines.len(), LazySource::line_count)
+    }
+
+    pub fn line_len(&self, row: usize) -> usize {
+        self.line(row).map_or(0, Line::grapheme_count)
+    }
+
+    pub fn find(
+        &self,
+        query: &Query,
+        from: Location,
+        direction: SearchDirection,
+    ) -> Option<Location> {
+        self.scan(from, direction, |y, line, bound| {
+            match direction {
+                SearchDirection::Forward => line.search_forward(query, bound),
+                SearchDirection::Backward => line.search_backward(query, bound),
+            }
+            .map(|x| Location { x, y })
+        })
+    }
+
+    /// Finds the next match of `re` from `from` along with the spans of its
+    /// capture groups.
+    pub fn find_regex(
+        &self,
+        re: &Regex,
+        from: Location,
+        direction: SearchDirection,
+    ) -> Option<RegexMatch> {
+        self.scan(from, direction, |y, line, bound| {
+            let mut matches = line.captures(re).into_iter().filter_map(|groups| {
+                let range = groups.first()?.clone()?;
+                Some(RegexMatch {
+                    row: y,
+                    range,
+                    groups,
+                })
+            });
+            match direction {
+                SearchDirection::Forward => matches.find(|found| found.range.start >= bound),
+                SearchDirection::Backward => matches.rev().find(|found| found.range.start < bound),
+            }
+        })
+    }
+
+    /// Runs `visit` on the lines from `from` on in `direction` until it finds
+    /// something. Besides the row and line, it gets the grapheme index that
+    /// matches must start at or after going forward, or before going back.
+    fn scan<T, F>(&self, from: Location, direction: SearchDirection, mut visit: F) -> Option<T>
+    where
+        F: FnMut(usize, &Line, usize) -> Option<T>,
+    {
+        match direction {
+            SearchDirection::Forward => {
+                let mut chunk_start = from.y;
+                while chunk_start < self.line_count() {
+                    let chunk_end = chunk_start.saturating_add(SEARCH_CHUNK);
+                    let found = self.visit_lines(chunk_start..chunk_end, false, |y, line| {
+                        let start = if y == from.y { from.x } else { 0 };
+                        visit(y, line, start)
+                    });
+                    if found.is_some() {
+                        return found;
+                    }
+                    chunk_start = chunk_end;
+                }
+            }
+            SearchDirection::Backward => {
+                let mut chunk_end = min(from.y.saturating_add(1), self.line_count());
+                while chunk_end > 0 {
+                    let chunk_start = chunk_end.saturating_sub(SEARCH_CHUNK);
+                    let found = self.visit_lines(chunk_start..chunk_end, true, |y, line| {
+                        let before = if y == from.y {
+                            from.x
+                        } else {
+                            line.grapheme_count().saturating_add(1)
+                        };
+                        visit(y, line, before)
+                    });
+                    if found.is_some() {
+                        return found;
+                    }
+                    chunk_end = chunk_start;
+                }
+            }
+        }
+        None
+    }
+
+    /// Like `find`, but carries on from the other end of the document if
+    /// nothing turns up before reaching its end. Also says whether it did.
+    pub fn find_wrapping(
+        &self,
+        query: &Query,
+        from: Location,
+        direction: SearchDirection,
+    ) -> Option<(Location, bool)> {
+        if let Some(found) = self.find(query, from, direction) {
+            return Some((found, false));
+        }
+        let restart = match direction {
+            SearchDirection::Forward => Location::default(),
+            SearchDirection::Backward => Location {
+                x: usize::MAX,
+                y: self.line_count().saturating_sub(1),
+            },
+        };
+        self.find(query, restart, direction)
+            .map(|found| (found, true))
+    }
+
+    /// Counts the matches of `query` in the whole document, returning how
+    /// many of them start before `at` and how many there are in all.
+    pub fn count_matches(&self, query: &Query, at: Location) -> (usize, usize) {
+        let mut before: usize = 0;
+        let mut total: usize = 0;
+        let mut chunk_start = 0;
+        while chunk_start < self.line_count() {
+            let chunk_end = chunk_start.saturating_add(SEARCH_CHUNK);
+            self.visit_lines(chunk_start..chunk_end, false, |y, line| -> Option<()> {
+                for range in line.find_all(query) {
+                    total = total.saturating_add(1);
+                    if (y, range.start) < (at.y, at.x) {
+                        before = before.saturating_add(1);
+                    }
+                }
+                None
+            });
+            chunk_start = chunk_end;
+        }
+        (before, total)
+    }
+
+    /// Calls `visit` on each line in `rows` until it returns something,
+    /// reading the lines from disk for lazily loaded files.
+    fn visit_lines<T, F>(&self, rows: Range<usize>, reverse: bool, mut visit: F) -> Option<T>
+    where
+        F: FnMut(usize, &Line) -> Option<T>,
+    {
+        let loaded;
+        let mut lines: Vec<(usize, &Line)> = if let Some(source) = &self.lazy {
+            loaded = source.read_lines(&rows).unwrap_or_default();
+            loaded
+                .iter()
+                .enumerate()
+                .map(|(idx, line)| (rows.start.saturating_add(idx), line))
+                .collect()
+        } else {
+            rows.filter_map(|y| self.line(y).map(|line| (y, line)))
+                .collect()
+        };
+        if reverse {
+            lines.reverse();
+        }
+        lines.into_iter().find_map(|(y, line)| visit(y, line))
+    }
+
+    /// Finds the partner of the bracket at `at`, counting nested pairs of the
+    /// same kind across lines. Returns `None` if `at` isn't on a bracket or
+    /// the bracket is unmatched.
+    pub fn is_bracket(&self, at: Location) -> bool {
+        self.line(at.y).is_some_and(|line| {
+            let byte_idx = line.grapheme_idx_to_byte_idx(at.x);
+            line.as_str()
+                .get(byte_idx..)
+                .and_then(|rest| rest.chars().next())
+                .is_some_and(|character| "()[]{}".contains(character))
+        })
+    }
+
+    pub fn matching_bracket(&self, at: Location) -> Option<Location> {
+        let line = self.line(at.y)?;
+        let start_byte_idx = line.grapheme_idx_to_byte_idx(at.x);
+        let bracket = line.as_str().get(start_byte_idx..)?.chars().next()?;
+        let (partner, direction) = match bracket {
+            '(' => (')', SearchDirection::Forward),
+            '[' => (']', SearchDirection::Forward),
+            '{' => ('}', SearchDirection::Forward),
+            ')' => ('(', SearchDirection::Backward),
+            ']' => ('[', SearchDirection::Backward),
+            '}' => ('{', SearchDirection::Backward),
+            _ => return None,
+        };
+        let mut depth: usize = 0;
+        let mut y = at.y;
+        loop {
+            let line = self.line(y)?;
+            let mut candidates: Vec<(usize, char)> = line
+                .as_str()
+                .char_indices()
+                .filter(|&(byte_idx, character)| {
+                    (character == bracket || character == partner)
+                        && (y != at.y
+                            || match direction {
+                                SearchDirection::Forward => byte_idx > start_byte_idx,
+                                SearchDirection::Backward => byte_idx < start_byte_idx,
+                            })
+                })
+                .collect();
+            if direction == SearchDirection::Backward {
+                candidates.reverse();
+            }
+            for (byte_idx, character) in candidates {
+                if character == bracket {
+                    depth = depth.saturating_add(1);
+                } else if depth > 0 {
+                    depth = depth.saturating_sub(1);
+                } else if let Some(x) = line.byte_idx_to_grapheme_idx(byte_idx) {
+                    return Some(Location { x, y });
+                }
+            }
+            y = match direction {
+                SearchDirection::Forward => y.saturating_add(1),
+                SearchDirection::Backward => y.checked_sub(1)?,
+            };
+        }
+    }
+
+    /// Returns the start of the next or previous word, stopping at the ends
+    /// of the line before crossing onto the adjacent one.
+    pub fn word_boundary(&self, at: Location, direction: SearchDirection) -> Location {
+        let Some(line) = self.line(at.y) else {
+            return at;
+        };
+        let line_len = line.grapheme_count();
+        let word_starts = line.word_starts();
+        match direction {
+            SearchDirection::Forward => {
+                if let Some(&x) = word_starts.iter().find(|&&start| start > at.x) {
+                    Location { x, y: at.y }
+                } else if at.x < line_len {
+                    Location {
+                        x: line_len,
+                        y: at.y,
+                    }
+                } else if at.y.saturating_add(1) < self.line_count() {
+                    Location {
+                        x: 0,
+                        y: at.y.saturating_add(1),
+                    }
+                } else {
+                    at
+                }
+            }
+            SearchDirection::Backward => {
+                if let Some(&x) = word_starts.iter().rev().find(|&&start| start < at.x) {
+                    Location { x, y: at.y }
+                } else if at.x > 0 {
+                    Location { x: 0, y: at.y }
+                } else if let Some(y) = at.y.checked_sub(1) {
+                    Location {
+                        x: self.line_len(y),
+                        y,
+                    }
+                } else {
+                    at
+                }
+            }
+        }
+    }
+
+    pub fn insert_char(&mut self, character: char, at: Location) -> Location {
+        let (mut edits, end) = self.insert_text(at, &character.to_string());
+        if edits.len() == 1
+            && let Some(edit) = edits.pop()
+        {
+            self.history.record_typed(edit);
+        } else {
+            self.history.record(edits);
+        }
+        end
+    }
+
+    /// Where the text of line `row` starts after its indentation, which is
+    /// the end of the line if it is blank.
+    pub fn first_non_blank(&self, row: usize) -> usize {
+        self.line(row).map_or(0, |line| {
+            let text = line.as_str();
+            line.byte_idx_to_grapheme_idx(text.len().saturating_sub(text.trim_start().len()))
+                .unwrap_or_else(|| line.grapheme_count())
+        })
+    }
+
+    /// Splits the line at `at`. The new line starts with the indentation of
+    /// the current one, plus `indent_unit` after an opening brace in C-like
+    /// files; blank lines pass on no indentation.
+    pub fn insert_newline(&mut self, at: Location, indent_unit: &str) -> Location {
+        let mut text = String::from("\n");
+        if let Some(line) = self.line(at.y)
+            && !line.as_str().trim().is_empty()
+        {
+            let before_caret = &line.as_str()[..line.grapheme_idx_to_byte_idx(at.x)];
+            let rest = before_caret.trim_start();
+            text.push_str(&before_caret[..before_caret.len().saturating_sub(rest.len())]);
+            if matches!(self.file_type, FileType::Rust | FileType::C)
+                && rest.trim_end().ends_with('{')
+            {
+                text.push_str(indent_unit);
+            }
+        }
+        let (edits, end) = self.insert_text(at, &text);
+        self.history.record(edits);
+        end
+    }
+
+    /// Inserts `text`, which may span several lines, and returns the location
+    /// right after it.
+    pub fn insert_str(&mut self, text: &str, at: Location) -> Location {
+        let (edits, end) = self.insert_text(at, text);
+        self.history.record(edits);
+        end
+    }
+
+    /// Replaces the `len` graphemes starting at `at` with `with` as a single
+    /// undo step and returns the location right after the replacement.
+    pub fn replace_range(&mut self, at: Location, len: usize, with: &str) -> Location {
+        let end = Location {
+            x: at.x.saturating_add(len),
+            y: at.y,
+        };
+        let mut edits = vec![self.apply_delete(at, end)];
+        let mut caret = at;
+        if !with.is_empty() {
+            let (inserted, end) = self.insert_text(at, with);
+            edits.extend(inserted);
+            caret = end;
+        }
+        self.history.record(edits);
+        caret
+    }
+
+    /// Replaces a regex match with `template`, in which `$1` to `$9` stand
+    /// for the capture groups, `$0` for the whole match and `$$` for `$`.
+    /// Returns the location right after the replacement.
+    pub fn replace_regex_match(&mut self, found: &RegexMatch, template: &str) -> Location {
+        let Some(line) = self.line(found.row) else {
+            return found.start();
+        };
+        let mut replacement = String::new();
+        let mut chars = template.chars().peekable();
+        while let Some(character) = chars.next() {
+            if character != '$' {
+                replacement.push(character);
+                continue;
+            }
+            match chars.peek().copied() {
+                Some('$') => {
+                    chars.next();
+                    replacement.push('$');
+                }
+                Some(digit @ '0'..='9') => {
+                    chars.next();
+                    #[allow(clippy::as_conversions)]
+                    let group_idx = digit.to_digit(10).unwrap_or_default() as usize;
+                    if let Some(Some(range)) = found.groups.get(group_idx) {
+                        let start = line.grapheme_idx_to_byte_idx(range.start);
+                        let end = line.grapheme_idx_to_byte_idx(range.end);
+                        replacement.push_str(&line.as_str()[start..end]);
+                    }
+                }
+                _ => replacement.push('$'),
+            }
+        }
+        self.replace_range(found.start(), found.range.len(), &replacement)
+    }
+
+    /// Removes the line at `row` and returns its contents, newline included.
+    pub fn remove_line(&mut self, row: usize) -> Option<String> {
+        let line = self.lines.get(row)?.to_string();
+        let line_end = Location {
+            x: self.line_len(row),
+            y: row,
+        };
+        let (start, end) = if row.saturating_add(1) < self.lines.len() {
+            (
+                Location { x: 0, y: row },
+                Location {
+                    x: 0,
+                    y: row.saturating_add(1),
+                },
+            )
+        } else if let Some(previous) = row.checked_sub(1) {
+            // The last line has no newline of its own, so take the one before it.
+            (
+                Location {
+                    x: self.line_len(previous),
+                    y: previous,
+                },
+                line_end,
+            )
+        } else {
+            (Location { x: 0, y: row }, line_end)
+        };
+        let edit = self.apply_delete(start, end);
+        self.history.record(vec![edit]);
+        Some(format!("{line}\n"))
+    }
+
+    /// Deletes from `at` to the end of its line as one undo step and returns
+    /// what was deleted. If `at` is already at the end of the line, the next
+    /// line is joined onto it instead, as Emacs' kill-line does; at the end
+    /// of the last line nothing happens.
+    pub fn truncate_line(&mut self, at: Location) -> String {
+        let at = self.clamp_location(at);
+        let line_len = self.line_len(at.y);
+        let end = if at.x < line_len {
+            Location {
+                x: line_len,
+                y: at.y,
+            }
+        } else {
+            Location {
+                x: 0,
+                y: at.y.saturating_add(1),
+            }
+        };
+        if end.y >= self.line_count() {
+            return String::new();
+        }
+        self.delete_range(at, end)
+    }
+
+    /// Deletes from the start of `at`'s line up to `at` as one undo step and
+    /// returns what was deleted.
+    pub fn truncate_line_head(&mut self, at: Location) -> String {
+        let at = self.clamp_location(at);
+        self.delete_range(Location { x: 0, y: at.y }, at)
+    }
+
+    /// Changes the case of the text from `start` to `end` as one undo step and
+    /// returns where the changed text now ends, since case mapping can change
+    /// its length.
+    pub fn transform_range(
+        &mut self,
+        start: Location,
+        end: Location,
+        kind: CaseTransform,
+    ) -> Location {
+        let text = self.text_range(start, end);
+        let transformed = kind.apply(&text);
+        if transformed == text {
+            return end;
+        }
+        let mut edits = vec![self.apply_delete(start, end)];
+        let (inserted, new_end) = self.insert_text(start, &transformed);
+        edits.extend(inserted);
+        self.history.record(edits);
+        new_end
+    }
+
+    /// Comments out the lines in `rows` by putting `prefix` and a space after
+    /// their indentation, or uncomments them if the first one is already
+    /// commented, so the block always flips as a whole. Blank lines are left
+    /// alone. All of it is one undo step.
+    pub fn toggle_comment(&mut self, rows: RangeInclusive<usize>, prefix: &str) {
+        let marker = format!("{prefix} ");
+        let Some(first) = self.line(*rows.start()) else {
+            return;
+        };
+        let uncomment = first.as_str().trim_start().starts_with(prefix);
+        let mut edits = Vec::new();
+        for y in rows {
+            let Some(line) = self.line(y) else {
+                break;
+            };
+            let text = line.as_str().trim_start();
+            let removed_len = if text.starts_with(&marker) {
+                marker.chars().count()
+            } else if text.starts_with(prefix) {
+                prefix.chars().count()
+            } else {
+                0
+            };
+            if text.is_empty() || (uncomment && removed_len == 0) {
+                continue;
+            }
+            let at = Location {
+                x: self.first_non_blank(y),
+                y,
+            };
+            edits.push(if uncomment {
+                let end = Location {
+                    x: at.x.saturating_add(removed_len),
+                    y,
+                };
+                self.apply_delete(at, end)
+            } else {
+                self.apply_insert(at, &marker)
+            });
+        }
+        self.history.record(edits);
+    }
+
+    /// The lines of the indented block around `row`: a line followed by more
+    /// deeply indented ones, along with a closing bracket line at its own
+    /// indentation if one ends the block. If `row` doesn't start a block, the
+    /// one it belongs to is used.
+    pub fn indented_block(&self, row: usize) -> Option<RangeInclusive<usize>> {
+        let is_blank = |y: usize| {
+            self.line(y)
+                .is_none_or(|line| line.as_str().trim().is_empty())
+        };
+        let next_non_blank = |from: usize| (from..self.line_count()).find(|&y| !is_blank(y));
+        let indent = |y: usize| self.first_non_blank(y);
+        let starts_block = !is_blank(row)
+            && next_non_blank(row.saturating_add(1)).is_some_and(|next| indent(next) > indent(row));
+        let header = if starts_block {
+            row
+        } else {
+            let own_indent = if is_blank(row) {
+                next_non_blank(row).map_or(0, indent)
+            } else {
+                indent(row)
+            };
+            (0..row)
+                .rev()
+                .find(|&y| !is_blank(y) && indent(y) < own_indent)?
+        };
+        let header_indent = indent(header);
+        let mut end = header;
+        for y in header.saturating_add(1)..self.line_count() {
+            if is_blank(y) {
+                continue;
+            }
+            if indent(y) > header_indent {
+                end = y;
+                continue;
+            }
+            if indent(y) == header_indent
+                && self
+                    .line(y)
+                    .is_some_and(|line| line.as_str().trim_start().starts_with([')', ']', '}']))
+            {
+                end = y;
+            }
+            break;
+        }
+        (end > header).then_some(header..=end)
+    }
+
+    /// The start and end of the word under or just before `at`.
+    pub fn word_at(&self, at: Location) -> Option<(Location, Location)> {
+        let range = self.line(at.y)?.word_at(at.x)?;
+        Some((
+            Location {
+                x: range.start,
+                y: at.y,
+            },
+            Location {
+                x: range.end,
+                y: at.y,
+            },
+        ))
+    }
+
+    /// Copies line `row` onto a new line right below it as one undo step.
+    /// Returns whether there was such a line.
+    pub fn duplicate_line(&mut self, row: usize) -> bool {
+        let Some(line) = self.line(row) else {
+            return false;
+        };
+        let text = format!("\n{line}");
+        let at = Location {
+            x: line.grapheme_count(),
+            y: row,
+        };
+        let (edits, _) = self.insert_text(at, &text);
+        self.history.record(edits);
+        true
+    }
+
+    /// Joins line `row` with the next one as one undo step, putting a single
+    /// space in place of the line break and the next line's indentation.
+    /// Returns where the lines were joined, or `None` for the last line.
+    pub fn join_lines(&mut self, row: usize) -> Option<Location> {
+        let next_row = row.saturating_add(1);
+        let line = self.line(row)?;
+        let next_line = self.line(next_row)?;
+        let needs_space = !line.as_str().ends_with(char::is_whitespace)
+            && !line.as_str().is_empty()
+            && !next_line.as_str().trim().is_empty();
+        let at = Location {
+            x: line.grapheme_count(),
+            y: row,
+        };
+        let end = Location {
+            x: self.first_non_blank(next_row),
+            y: next_row,
+        };
+        let mut edits = vec![self.apply_delete(at, end)];
+        if needs_space {
+            let (inserted, _) = self.insert_text(at, " ");
+            edits.extend(inserted);
+        }
+        self.history.record(edits);
+        Some(at)
+    }
+
+    /// Swaps line `row` with the one below or above it as one undo step.
+    /// Returns whether there was a neighbor to swap with.
+    pub fn move_line(&mut self, row: usize, direction: SearchDirection) -> bool {
+        let upper = match direction {
+            SearchDirection::Forward => row,
+            SearchDirection::Backward => {
+                let Some(upper) = row.checked_sub(1) else {
+                    return false;
+                };
+                upper
+            }
+        };
+        let lower = upper.saturating_add(1);
+        let Some(lower_text) = self.line(lower).map(ToString::to_string) else {
+            return false;
+        };
+        // Take the lower line out along with the newline before it, then put
+        // it back above the upper one.
+        let removed = self.apply_delete(
+            Location {
+                x: self.line_len(upper),
+                y: upper,
+            },
+            Location {
+                x: self.line_len(lower),
+                y: lower,
+            },
+        );
+        let inserted = self.apply_insert(Location { x: 0, y: upper }, &format!("{lower_text}\n"));
+        self.history.record(vec![removed, inserted]);
+        true
+    }
+
+    /// Returns the text between `start` and `end`, joining lines with `\n`.
+    pub fn text_range(&self, start: Location, end: Location) -> String {
+        let (Some(first), Some(last)) = (self.line(start.y), self.line(end.y)) else {
+            return String::new();
+        };
+        if (end.y, end.x) <= (start.y, start.x) {
+            return String::new();
+        }
+        let start_byte_idx = first.grapheme_idx_to_byte_idx(start.x);
+        let end_byte_idx = last.grapheme_idx_to_byte_idx(end.x);
+        if start.y == end.y {
+            return first.as_str()[start_byte_idx..end_byte_idx].to_string();
+        }
+        let mut text = first.as_str()[start_byte_idx..].to_string();
+        for line in (start.y.saturating_add(1)..end.y).filter_map(|y| self.line(y)) {
+            text.push('\n');
+            text.push_str(line.as_str());
+        }
+        text.push('\n');
+        text.push_str(&last.as_str()[..end_byte_idx]);
+        text
+    }
+
+    pub fn delete_range(&mut self, start: Location, end: Location) -> String {
+        let edit = self.apply_delete(start, end);
+        let text = edit.text().to_string();
+        if !text.is_empty() {
+            self.history.record(vec![edit]);
+        }
+        text
+    }
+
+    /// Moves `location` onto existing text; the line just past the end of
+    /// the buffer maps to the end of the last line.
+    pub fn clamp_location(&self, location: Location) -> Location {
+        if location.y < self.line_count() {
+            return Location {
+                x: min(location.x, self.line_len(location.y)),
+                y: location.y,
+            };
+        }
+        let y = self.line_count().saturating_sub(1);
+        Location {
+            x: self.line_len(y),
+            y,
+        }
+    }
+
+    pub fn delete(&mut self, at: Location) {
+        let line_len = self.line_len(at.y);
+        let end = if at.x < line_len {
+            Location {
+                x: at.x.saturating_add(1),
+                y: at.y,
+            }
+        } else if at.y.saturating_add(1) < self.lines.len() {
+            Location {
+                x: 0,
+                y: at.y.saturating_add(1),
+            }
+        } else {
+            return;
+        };
+        let start = Location {
+            x: min(at.x, line_len),
+            y: at.y,
+        };
+        let edit = self.apply_delete(start, end);
+        self.history.record(vec![edit]);
+    }
+
+    pub fn backspace(&mut self, at: Location) -> Option<Location> {
+        let previous = if at.x > 0 {
+            Location {
+                x: at.x.saturating_sub(1),
+                y: at.y,
+            }
+        } else if at.y > 0 {
+            let y = at.y.saturating_sub(1);
+            let x = self.line_len(y);
+            Location { x, y }
+        } else {
+            return None;
+        };
+        self.delete(previous);
+        Some(previous)
+    }
+
+    pub fn set_mark(&mut self, name: char, at: Location) {
+        self.marks.set(name, at);
+    }
+
+    pub fn mark(&self, name: char) -> Option<Location> {
+        self.marks.get(name).map(|at| self.clamp_location(at))
+    }
+
+    /// Remembers that the closer at `at` was typed by `auto_pairs`.
+    pub fn add_auto_closer(&mut self, at: Location) {
+        self.marks.add_closer(at);
+    }
+
+    pub fn is_auto_closer(&self, at: Location) -> bool {
+        self.marks.is_closer(at)
+    }
+
+    pub fn forget_auto_closer(&mut self, at: Location) {
+        self.marks.forget_closer(at);
+    }
+
+    pub fn forget_auto_closers(&mut self) {
+        self.marks.forget_closers();
+    }
+
+    pub fn push_jump(&mut self, from: Location) {
+        self.marks.push_jump(from);
+    }
+
+    pub fn jump_back(&mut self, current: Location) -> Option<Location> {
+        self.marks.back(current).map(|at| self.clamp_location(at))
+    }
+
+    pub fn jump_forward(&mut self) -> Option<Location> {
+        self.marks.forward().map(|at| self.clamp_location(at))
+    }
+
+    /// Marks the point from which `merge_undo_steps` groups edits.
+    pub const fn undo_checkpoint(&self) -> usize {
+        self.history.current()
+    }
+
+    /// Turns everything recorded since `checkpoint` into one undo step.
+    pub fn merge_undo_steps(&mut self, checkpoint: usize) {
+        self.history.merge_since(checkpoint);
+    }
+
+    /// Reverts the most recent undo step and returns where it happened.
+    pub fn undo(&mut self) -> Option<Location> {
+        let edits = self.history.undo()?;
+        Some(self.revert(&edits))
+    }
+
+    /// Reapplies the most recently undone step and returns where it ended.
+    pub fn redo(&mut self) -> Option<Location> {
+        let edits = self.history.redo()?;
+        Some(self.reapply(&edits))
+    }
+
+    /// Goes to the state of the buffer before the current change, or after
+    /// it with `later`, across undo branches, and returns the caret for it.
+    pub fn travel(&mut self, later: bool) -> Option<Location> {
+        let path = self.history.travel(later)?;
+        let mut caret = None;
+        for edits in &path.revert {
+            caret = Some(self.revert(edits));
+        }
+        for edits in &path.apply {
+            caret = Some(self.reapply(edits));
+        }
+        caret
+    }
+
+    pub fn undo_list(&self) -> UndoList {
+        self.history.list()
+    }
+
+    fn revert(&mut self, edits: &[Edit]) -> Location {
+        for edit in edits.iter().rev() {
+            match edit {
+                Edit::Insert { at, end, .. } => {
+                    self.remove_range(*at, *end);
+                }
+                Edit::Delete { at, text, .. } => {
+                    self.insert_raw(*at, text);
+                }
+            }
+        }
+        edits.first().map(Edit::start).unwrap_or_default()
+    }
+
+    fn reapply(&mut self, edits: &[Edit]) -> Location {
+        for edit in edits {
+            match edit {
+                Edit::Insert { at, text, .. } => {
+                    self.insert_raw(*at, text);
+                }
+                Edit::Delete { at, end, .. } => {
+                    self.remove_range(*at, *end);
+                }
+            }
+        }
+        edits.last().map(Edit::caret_after).unwrap_or_default()
+    }
+
+    /// Inserts `text` at `at` without recording it. When `at` is the empty
+    /// line just past the end of the buffer, that line is first made real by
+    /// appending a newline to the last line.
+    fn insert_text(&mut self, at: Location, text: &str) -> (Vec<Edit>, Location) {
+        let mut edits = Vec::new();
+        if at.y > self.lines.len() {
+            return (edits, at);
+        }
+        if at.y == self.lines.len()
+            && let Some(last_idx) = at.y.checked_sub(1)
+        {
+            let end_of_last = Location {
+                x: self.line_len(last_idx),
+                y: last_idx,
+            };
+            edits.push(self.apply_insert(end_of_last, "\n"));
+        }
+        let at = Location {
+            x: min(at.x, self.line_len(at.y)),
+            y: at.y,
+        };
+        let edit = self.apply_insert(at, text);
+        let end = edit.caret_after();
+        edits.push(edit);
+        (edits, end)
+    }
+
+    fn apply_insert(&mut self, at: Location, text: &str) -> Edit {
+        let end = self.insert_raw(at, text);
+        Edit::Insert {
+            at,
+            end,
+            text: text.to_string(),
+        }
+    }
+
+    fn apply_delete(&mut self, at: Location, end: Location) -> Edit {
+        let text = self.remove_range(at, end);
+        Edit::Delete { at, end, text }
+    }
+
+    fn insert_raw(&mut self, at: Location, text: &str) -> Location {
+        if self.lines.is_empty() {
+            self.lines.push(Line::default());
+        }
+        let Some(line) = self.lines.get(at.y) else {
+            return at;
+        };
+        let (head, tail) = line.as_str().split_at(line.grapheme_idx_to_byte_idx(at.x));
+        let tail = tail.to_string();
+        let mut segments = text.split('\n');
+        let mut new_lines = vec![format!("{head}{}", segments.next().unwrap_or_default())];
+        new_lines.extend(segments.map(String::from));
+        let end = Location {
+            x: new_lines
+                .last()
+                .map_or(0, |last| Line::from(last).grapheme_count()),
+            y: at.y.saturating_add(new_lines.len()).saturating_sub(1),
+        };
+        if let Some(last) = new_lines.last_mut() {
+            last.push_str(&tail);
+        }
+        self.lines.splice(
+            at.y..=at.y,
+            new_lines.iter().map(|new_line| Line::from(new_line)),
+        );
+        self.marks.shift_for_insert(at, end);
+        self.note_change(at.y);
+        self.dirty = true;
+        end
+    }
+
+    fn remove_range(&mut self, start: Location, end: Location) -> String {
+        let removed = self.text_range(start, end);
+        let (Some(first), Some(last)) = (self.lines.get(start.y), self.lines.get(end.y)) else {
+            return String::new();
+        };
+        if removed.is_empty() {
+            return removed;
+        }
+        let joined = format!(
+            "{}{}",
+            &first.as_str()[..first.grapheme_idx_to_byte_idx(start.x)],
+            &last.as_str()[last.grapheme_idx_to_byte_idx(end.x)..]
+        );
+        self.lines
+            .splice(start.y..=end.y, std::iter::once(Line::from(&joined)));
+        self.marks.shift_for_remove(start, end);
+        self.note_change(start.y);
+        self.dirty = true;
+        if self.lines.len() == 1 && self.lines.first().is_some_and(Line::is_empty) {
+            self.lines.clear();
+        }
+        removed
+    }
+}
+
+fn modified_time(file_name: &str) -> Option<SystemTime> {
+    metadata(file_name)
+        .and_then(|metadata| metadata.modified())
+        .ok()
+}
+
+/// Writes `contents` to a temporary file next to `path` and renames it over
+/// `path`, so that an interrupted save leaves the old file intact. The data
+/// is flushed to disk before the rename, and the rename itself after it, so
+/// that a crash can't leave the new name pointing at unwritten data. The new
+/// file keeps the old one's permissions, and a symlink keeps pointing at it.
+fn write_atomically(path: &Path, contents: &str) -> Result<(), Error> {
+    let target = canonicalize(path).unwrap_or_else(|_| path.to_path_buf());
+    let temp = temp_path(&target);
+    let result = write_synced(&temp, contents).and_then(|()| {
+        if let Ok(metadata) = metadata(&target) {
+            let _ = set_permissions(&temp, metadata.permissions());
+        }
+        replace_file(&temp, &target)?;
+        sync_parent_dir(&target)
+    });
+    if result.is_err() {
+        let _ = remove_file(&temp);
+    }
+    result
+}
+
+fn temp_path(target: &Path) -> PathBuf {
+    let mut temp_name = target.file_name().unwrap_or_default().to_os_string();
+    temp_name.push(format!(".{}.tmp", std::process::id()));
+    target.with_file_name(temp_name)
+}
+
+fn write_synced(path: &Path, contents: &str) -> Result<(), Error> {
+    let mut file = File::create(path)?;
+    file.write_all(contents.as_bytes())?;
+    file.sync_all()
+}
+
+/// Makes a rename in the directory holding `path` durable.
+#[cfg(unix)]
+fn sync_parent_dir(path: &Path) -> Result<(), Error> {
+    let parent = path
+        .parent()
+        .filter(|parent| !parent.as_os_str().is_empty())
+        .unwrap_or_else(|| Path::new("."));
+    File::open(parent)?.sync_all()
+}
+
+/// Directories can't be opened to be synced outside Unix.
+#[cfg(not(unix))]
+fn sync_parent_dir(_path: &Path) -> Result<(), Error> {
+    Ok(())
+}
+
+#[cfg(not(windows))]
+fn replace_file(from: &Path, to: &Path) -> Result<(), Error> {
+    rename(from, to)
+}
+
+/// Windows can refuse to rename over a file another program holds open, in
+/// which case copying over it in place may still succeed. That copy is not
+/// atomic: a crash in the middle of it can leave the file partly written.
+#[cfg(windows)]
+fn replace_file(from: &Path, to: &Path) -> Result<(), Error> {
+    rename(from, to).or_else(|_| {
+        copy(from, to)?;
+        std::fs::OpenOptions::new()
+            .write(true)
+            .open(to)?
+            .sync_all()?;
+        remove_file(from)
+    })
+}
+
+/// The lines in memory joined with the buffer's line ending.
+impl Display for Buffer {
+    fn fmt(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        for (row, line) in self.iter_lines().enumerate() {
+            if row > 0 {
+                write!(formatter, "{}", self.line_ending.as_str())?;
+            }
+            write!(formatter, "{line}")?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::super::Location;
+    use super::super::Options;
+    use super::{Buffer, temp_path, write_atomically};
+    use std::fs::{canonicalize, create_dir, create_dir_all, read, remove_dir_all, write};
+    use std::path::PathBuf;
+
+    /// An empty directory of its own for each test.
+    fn scratch_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("rustamundo-{}-{name}", std::process::id()));
+        let _ = remove_dir_all(&dir);
+        create_dir_all(&dir).unwrap();
+        // Saving resolves symlinks, so the temp file's name must too.
+        canonicalize(dir).unwrap()
+    }
+
+    fn lines(buffer: &Buffer) -> Vec<String> {
+        (0..buffer.line_count())
+            .filter_map(|row| buffer.line(row))
+            .map(|line| line.as_str().to_string())
+            .collect()
+    }
+
+    #[test]
+    fn insert_str_splits_a_line_in_the_middle() {
+        let mut buffer = Buffer::from_string("hello world");
+        let end = buffer.insert_str("a\nb\nc", Location { x: 5, y: 0 });
+        assert_eq!(end, Location { x: 1, y: 2 });
+        assert_eq!(lines(&buffer), ["helloa", "b", "c world"]);
+    }
+
+    #[test]
+    fn insert_str_at_line_start_and_end() {
+        let mut buffer = Buffer::from_string("one\ntwo");
+        let end = buffer.insert_str("x\ny", Location { x: 0, y: 1 });
+        assert_eq!(end, Location { x: 1, y: 2 });
+        assert_eq!(lines(&buffer), ["one", "x", "ytwo"]);
+        let end = buffer.insert_str("!\n?", Location { x: 3, y: 0 });
+        assert_eq!(end, Location { x: 1, y: 1 });
+        assert_eq!(lines(&buffer), ["one!", "?", "x", "ytwo"]);
+    }
+
+    #[test]
+    fn insert_str_ending_in_a_newline() {
+        let mut buffer = Buffer::from_string("abc");
+        let end = buffer.insert_str("new\n", Location { x: 1, y: 0 });
+        assert_eq!(end, Location { x: 0, y: 1 });
+        assert_eq!(lines(&buffer), ["anew", "bc"]);
+    }
+
+    #[test]
+    fn failed_write_leaves_the_original_alone() {
+        let dir = scratch_dir("failed-write");
+        let path = dir.join("file.txt");
+        write(&path, "original").unwrap();
+        // A directory in the temp file's place makes writing it fail.
+        create_dir(temp_path(&path)).unwrap();
+        assert!(write_atomically(&path, "replacement").is_err());
+        assert_eq!(read(&path).unwrap(), b"original");
+        remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn stray_temp_file_is_replaced_not_kept() {
+        let dir = scratch_dir("stray-temp");
+        let path = dir.join("file.txt");
+        write(&path, "original").unwrap();
+        // What a save killed halfway through would leave behind.
+        write(temp_path(&path), "replace").unwrap();
+        assert_eq!(read(&path).unwrap(), b"original");
+        write_atomically(&path, "replacement").unwrap();
+        assert_eq!(read(&path).unwrap(), b"replacement");
+        assert!(!temp_path(&path).exists());
+        remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn auto_closers_follow_edits_and_vanish_with_their_text() {
+        let mut buffer = Buffer::from_string("f()\nnext line");
+        let closer = Location { x: 2, y: 0 };
+        buffer.add_auto_closer(closer);
+        buffer.truncate_line(Location { x: 4, y: 1 });
+        buffer.insert_str("pasted\n", Location { x: 0, y: 1 });
+        assert!(buffer.is_auto_closer(closer));
+        buffer.insert_str("oo", Location { x: 1, y: 0 });
+        assert!(!buffer.is_auto_closer(closer));
+        assert!(buffer.is_auto_closer(Location { x: 4, y: 0 }));
+        buffer.delete_range(Location { x: 3, y: 0 }, Location { x: 5, y: 0 });
+        assert!(!buffer.is_auto_closer(Location { x: 3, y: 0 }));
+        assert!(!buffer.is_auto_closer(Location { x: 4, y: 0 }));
+    }
+
+    fn tidy() -> Options {
+        Options {
+            trim_trailing_whitespace: true,
+            ensure_final_newline: true,
+            ..Options::default()
+        }
+    }
+
+    #[test]
+    fn failed_save_leaves_the_buffer_untidied() {
+        let dir = scratch_dir("failed-save");
+        let mut buffer = Buffer::from_string("text  \n\n");
+        buffer.file_name = Some(dir.join("missing").join("file.txt").display().to_string());
+        assert!(buffer.save(tidy(), Location::default()).is_err());
+        assert_eq!(lines(&buffer), ["text  ", ""]);
+        assert!(!buffer.is_dirty());
+        remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn save_tidies_what_it_writes() {
+        let dir = scratch_dir("tidy-save");
+        let path = dir.join("file.txt");
+        let mut buffer = Buffer::from_string("text  \nmore\t\n\n");
+        buffer.file_name = Some(path.display().to_string());
+        buffer.save(tidy(), Location::default()).unwrap();
+        assert_eq!(read(&path).unwrap(), b"text\nmore\n");
+        assert_eq!(lines(&buffer), ["text", "more"]);
+        assert!(!buffer.is_dirty());
+        remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn save_keeps_the_final_line_ending_and_line_endings() {
+        let dir = scratch_dir("round-trip");
+        for (name, contents) in [("lf", "a\nb\n"), ("crlf", "a\r\nb\r\n"), ("none", "a\nb")] {
+            let path = dir.join(name);
+            write(&path, contents).unwrap();
+            let mut buffer = Buffer::load(&path.display().to_string()).unwrap();
+            buffer
+                .save(Options::default(), Location::default())
+                .unwrap();
+            assert_eq!(read(&path).unwrap(), contents.as_bytes(), "{name}");
+        }
+        remove_dir_all(&dir).unwrap();
     }
 }
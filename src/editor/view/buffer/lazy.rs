@@ -0,0 +1,151 @@
+use super::super::line::Line;
+use core::ops::Range;
+use std::fs::File;
+use std::io::{BufRead, BufReader, Error, Seek, SeekFrom};
+
+/// How many lines apart the lines whose offsets are indexed are. The lines
+/// in between are found by reading on from the one before them.
+const INDEX_STRIDE: usize = 1024;
+const READ_BUFFER_SIZE: usize = 1 << 16;
+
+/// A file that is indexed up front but only read a window of lines at a
+/// time, for files too large to hold in memory.
+pub struct LazySource {
+    file_name: String,
+    /// The byte offset of every `INDEX_STRIDE`th line, starting with the
+    /// first.
+    checkpoints: Vec<u64>,
+    line_count: usize,
+}
+
+impl LazySource {
+    /// Counts the lines and records where every `INDEX_STRIDE`th one
+    /// starts, reading the file in chunks rather than all at once.
+    pub fn scan(file_name: &str) -> Result<Self, Error> {
+        let file = File::open(file_name)?;
+        let file_len = file.metadata()?.len();
+        let mut reader = BufReader::with_capacity(READ_BUFFER_SIZE, file);
+        let mut checkpoints = Vec::new();
+        let mut line_count: usize = 0;
+        let mut offset: u64 = 0;
+        if file_len > 0 {
+            checkpoints.push(0);
+            line_count = 1;
+        }
+        loop {
+            let chunk = reader.fill_buf()?;
+            if chunk.is_empty() {
+                break;
+            }
+            let chunk_len = chunk.len();
+            for (idx, byte) in chunk.iter().enumerate() {
+                if *byte != b'\n' {
+                    continue;
+                }
+                let start = offset
+                    .saturating_add(u64::try_from(idx).unwrap_or(0))
+                    .saturating_add(1);
+                // A trailing newline doesn't start another line, just like
+                // in a full load.
+                if start >= file_len {
+                    continue;
+                }
+                if line_count.checked_rem(INDEX_STRIDE) == Some(0) {
+                    checkpoints.push(start);
+                }
+                line_count = line_count.saturating_add(1);
+            }
+            reader.consume(chunk_len);
+            offset = offset.saturating_add(u64::try_from(chunk_len).unwrap_or(0));
+        }
+        Ok(Self {
+            file_name: file_name.to_string(),
+            checkpoints,
+            line_count,
+        })
+    }
+
+    pub fn file_name(&self) -> &str {
+        &self.file_name
+    }
+
+    pub const fn line_count(&self) -> usize {
+        self.line_count
+    }
+
+    /// Reads the lines `rows`, or as many of them as the file has.
+    pub fn read_lines(&self, rows: &Range<usize>) -> Result<Vec<Line>, Error> {
+        let end = rows.end.min(self.line_count);
+        let checkpoint_idx = rows.start.checked_div(INDEX_STRIDE).unwrap_or_default();
+        let Some(&offset) = self.checkpoints.get(checkpoint_idx) else {
+            return Ok(Vec::new());
+        };
+        let mut file = File::open(&self.file_name)?;
+        file.seek(SeekFrom::Start(offset))?;
+        let mut reader = BufReader::with_capacity(READ_BUFFER_SIZE, file);
+        let mut bytes = Vec::new();
+        let first_row = checkpoint_idx.saturating_mul(INDEX_STRIDE);
+        for _ in first_row..rows.start {
+            bytes.clear();
+            reader.read_until(b'\n', &mut bytes)?;
+        }
+        let mut lines = Vec::with_capacity(end.saturating_sub(rows.start));
+        for _ in rows.start..end {
+            bytes.clear();
+            if reader.read_until(b'\n', &mut bytes)? == 0 {
+                break;
+            }
+            let line = String::from_utf8_lossy(&bytes);
+            let line = line.strip_suffix('\n').unwrap_or(&line);
+            lines.push(Line::from(line.strip_suffix('\r').unwrap_or(line)));
+        }
+        Ok(lines)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::super::line::Line;
+    use super::super::Buffer;
+    use super::{INDEX_STRIDE, LazySource};
+    use core::fmt::Write as _;
+    use std::fs::{remove_file, write};
+
+    #[test]
+    fn lines_are_found_across_index_checkpoints() {
+        let path = std::env::temp_dir().join(format!("rustamundo-{}-lazy", std::process::id()));
+        let line_count = INDEX_STRIDE.saturating_mul(3).saturating_add(5);
+        let contents = (0..line_count).fold(String::new(), |mut contents, row| {
+            let _ = write!(contents, "line {row}\r\n");
+            contents
+        });
+        write(&path, contents).unwrap();
+        let file_name = path.display().to_string();
+
+        let source = LazySource::scan(&file_name).unwrap();
+        assert_eq!(source.line_count(), line_count);
+        let start = INDEX_STRIDE.saturating_sub(2);
+        let lines = source
+            .read_lines(&(start..start.saturating_add(4)))
+            .unwrap();
+        let texts: Vec<&str> = lines.iter().map(Line::as_str).collect();
+        assert_eq!(texts, ["line 1022", "line 1023", "line 1024", "line 1025"]);
+        let tail = source
+            .read_lines(&(line_count.saturating_sub(1)..line_count.saturating_add(10)))
+            .unwrap();
+        assert_eq!(tail.len(), 1);
+
+        let mut buffer = Buffer::load_lazy(&file_name).unwrap();
+        for row in [
+            0,
+            INDEX_STRIDE.saturating_sub(1),
+            INDEX_STRIDE,
+            line_count.saturating_sub(1),
+        ] {
+            buffer.ensure_resident(row..row.saturating_add(1));
+            let expected = format!("line {row}");
+            assert_eq!(buffer.line(row).map(Line::as_str), Some(expected.as_str()));
+        }
+        remove_file(&path).unwrap();
+    }
+}
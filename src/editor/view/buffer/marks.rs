@@ -0,0 +1,114 @@
+use super::super::super::Location;
+use std::collections::HashMap;
+
+const MAX_JUMPS: usize = 100;
+
+/// The named marks, jump list and auto-inserted closers of a buffer. Edits
+/// move them along with the text they point at.
+#[derive(Default)]
+pub struct Marks {
+    named: HashMap<char, Location>,
+    /// Closers typed by `auto_pairs` that the user hasn't typed over yet.
+    /// Unlike marks, these go away when their text is deleted.
+    closers: Vec<Location>,
+    /// Where the caret was before each jump, oldest first.
+    jumps: Vec<Location>,
+    /// The entry of `jumps` that `back` last went to, or its length while
+    /// not going back through it.
+    jump_idx: usize,
+}
+
+impl Marks {
+    pub fn set(&mut self, name: char, at: Location) {
+        self.named.insert(name, at);
+    }
+
+    pub fn get(&self, name: char) -> Option<Location> {
+        self.named.get(&name).copied()
+    }
+
+    pub fn add_closer(&mut self, at: Location) {
+        self.closers.push(at);
+    }
+
+    pub fn is_closer(&self, at: Location) -> bool {
+        self.closers.contains(&at)
+    }
+
+    pub fn forget_closer(&mut self, at: Location) {
+        self.closers.retain(|&closer| closer != at);
+    }
+
+    pub fn forget_closers(&mut self) {
+        self.closers.clear();
+    }
+
+    /// Records a jump away from `from`, forgetting the jumps that were gone
+    /// back over.
+    pub fn push_jump(&mut self, from: Location) {
+        self.jumps.truncate(self.jump_idx);
+        if self.jumps.last() != Some(&from) {
+            self.jumps.push(from);
+        }
+        if self.jumps.len() > MAX_JUMPS {
+            self.jumps.remove(0);
+        }
+        self.jump_idx = self.jumps.len();
+    }
+
+    /// Steps back through the jump list from `current`, which is kept so
+    /// that `forward` can return to it.
+    pub fn back(&mut self, current: Location) -> Option<Location> {
+        let idx = self.jump_idx.checked_sub(1)?;
+        if self.jump_idx == self.jumps.len() {
+            self.jumps.push(current);
+        }
+        self.jump_idx = idx;
+        self.jumps.get(idx).copied()
+    }
+
+    pub fn forward(&mut self) -> Option<Location> {
+        let idx = self.jump_idx.saturating_add(1);
+        let to = self.jumps.get(idx).copied()?;
+        self.jump_idx = idx;
+        Some(to)
+    }
+
+    /// Moves the marks at or after `at` along with the text inserted there,
+    /// which ends at `end`.
+    pub fn shift_for_insert(&mut self, at: Location, end: Location) {
+        for mark in self.locations_mut() {
+            if mark.y == at.y && mark.x >= at.x {
+                mark.x = end.x.saturating_add(mark.x.saturating_sub(at.x));
+                mark.y = end.y;
+            } else if mark.y > at.y {
+                mark.y = mark.y.saturating_add(end.y.saturating_sub(at.y));
+            }
+        }
+    }
+
+    /// Moves the marks after the removed text from `start` to `end` back
+    /// over it; those inside it end up at `start`.
+    pub fn shift_for_remove(&mut self, start: Location, end: Location) {
+        self.closers.retain(|&closer| {
+            (closer.y, closer.x) < (start.y, start.x) || (closer.y, closer.x) >= (end.y, end.x)
+        });
+        for mark in self.locations_mut() {
+            if mark.y > end.y {
+                mark.y = mark.y.saturating_sub(end.y.saturating_sub(start.y));
+            } else if mark.y == end.y && mark.x >= end.x {
+                mark.x = start.x.saturating_add(mark.x.saturating_sub(end.x));
+                mark.y = start.y;
+            } else if mark.y > start.y || (mark.y == start.y && mark.x > start.x) {
+                *mark = start;
+            }
+        }
+    }
+
+    fn locations_mut(&mut self) -> impl Iterator<Item = &mut Location> {
+        self.named
+            .values_mut()
+            .chain(self.jumps.iter_mut())
+            .chain(self.closers.iter_mut())
+    }
+}
@@ -0,0 +1,245 @@
+use super::super::annotatedstring::{Annotation, AnnotationType};
+use super::super::filetype::FileType;
+use super::line::Line;
+
+const RUST_KEYWORDS: [&str; 39] = [
+    "as", "async", "await", "break", "const", "continue", "crate", "dyn", "else", "enum", "extern",
+    "false", "fn", "for", "if", "impl", "in", "let", "loop", "match", "mod", "move", "mut", "pub",
+    "ref", "return", "self", "Self", "static", "struct", "super", "trait", "true", "type",
+    "unsafe", "use", "where", "while", "yield",
+];
+
+const C_KEYWORDS: [&str; 32] = [
+    "auto", "break", "case", "char", "const", "continue", "default", "do", "double", "else",
+    "enum", "extern", "float", "for", "goto", "if", "int", "long", "register", "return", "short",
+    "signed", "sizeof", "static", "struct", "switch", "typedef", "union", "unsigned", "void",
+    "volatile", "while",
+];
+
+/// Highlights a document line by line. Lines must be fed in document order,
+/// since an open block comment carries over to the following lines.
+#[derive(Default, Clone)]
+pub struct Highlighter {
+    file_type: FileType,
+    block_comment_depth: usize,
+}
+
+impl Highlighter {
+    pub fn for_file_type(file_type: FileType) -> Self {
+        Self {
+            file_type,
+            block_comment_depth: 0,
+        }
+    }
+
+    /// How many block comments deep the next line starts.
+    pub const fn block_comment_depth(&self) -> usize {
+        self.block_comment_depth
+    }
+
+    /// A highlighter for the same file type about to highlight a line that
+    /// starts `block_comment_depth` block comments deep.
+    pub const fn resumed_at(&self, block_comment_depth: usize) -> Self {
+        Self {
+            file_type: self.file_type,
+            block_comment_depth,
+        }
+    }
+
+    /// Whether an edit on one line can change the highlighting of the lines
+    /// after it.
+    pub const fn spans_lines(&self) -> bool {
+        matches!(self.file_type, FileType::Rust | FileType::C)
+    }
+
+    pub fn highlight_line(&mut self, line: &Line) -> Vec<Annotation> {
+        match self.file_type {
+            FileType::Rust => self.highlight_code(line, &RUST_KEYWORDS),
+            FileType::C => self.highlight_code(line, &C_KEYWORDS),
+            FileType::Markdown => Self::highlight_markdown(line),
+            FileType::PlainText => Vec::new(),
+        }
+    }
+
+    /// Marks headings and inline code spans.
+    fn highlight_markdown(line: &Line) -> Vec<Annotation> {
+        if line.as_str().trim_start().starts_with('#') {
+            return vec![Annotation {
+                kind: AnnotationType::Keyword,
+                start: 0,
+                end: line.grapheme_count(),
+            }];
+        }
+        let mut annotations = Vec::new();
+        let mut code_start = None;
+        for (byte_idx, _) in line.as_str().match_indices('`') {
+            if let Some(start) = code_start.take() {
+                if let (Some(start), Some(end)) = (
+                    line.byte_idx_to_grapheme_idx(start),
+                    line.byte_idx_to_grapheme_idx(byte_idx.saturating_add(1)),
+                ) {
+                    annotations.push(Annotation {
+                        kind: AnnotationType::String,
+                        start,
+                        end,
+                    });
+                }
+            } else {
+                code_start = Some(byte_idx);
+            }
+        }
+        annotations
+    }
+
+    fn highlight_code(&mut self, line: &Line, keywords: &[&str]) -> Vec<Annotation> {
+        let text = line.as_str();
+        let chars: Vec<(usize, char)> = text.char_indices().collect();
+        let mut annotations = Vec::new();
+        let mut idx: usize = 0;
+        while let Some(&(start_byte_idx, character)) = chars.get(idx) {
+            let rest = &chars[idx..];
+            let next = rest.get(1).map(|&(_, next)| next);
+            let (len, kind) = if self.block_comment_depth > 0 {
+                (self.block_comment_len(rest), Some(AnnotationType::Comment))
+            } else if character == '/' && next == Some('/') {
+                (rest.len(), Some(AnnotationType::Comment))
+            } else if character == '/' && next == Some('*') {
+                (self.block_comment_len(rest), Some(AnnotationType::Comment))
+            } else if character == '"' {
+                (Self::string_len(rest), Some(AnnotationType::String))
+            } else if character == '\'' {
+                Self::char_literal_len(rest)
+                    .map_or((1, None), |len| (len, Some(AnnotationType::Character)))
+            } else if character.is_ascii_digit() {
+                (Self::number_len(rest), Some(AnnotationType::Number))
+            } else if character.is_alphanumeric() || character == '_' {
+                let len = Self::word_len(rest);
+                let word = &text[start_byte_idx..Self::byte_idx_at(&chars, idx, len, text)];
+                let kind = keywords.contains(&word).then_some(AnnotationType::Keyword);
+                (len, kind)
+            } else {
+                (1, None)
+            };
+            let len = len.max(1);
+            if let Some(kind) = kind {
+                let end_byte_idx = Self::byte_idx_at(&chars, idx, len, text);
+                if let (Some(start), Some(end)) = (
+                    line.byte_idx_to_grapheme_idx(start_byte_idx),
+                    line.byte_idx_to_grapheme_idx(end_byte_idx),
+                ) {
+                    annotations.push(Annotation { kind, start, end });
+                }
+            }
+            idx = idx.saturating_add(len);
+        }
+        annotations
+    }
+
+    fn byte_idx_at(chars: &[(usize, char)], idx: usize, len: usize, text: &str) -> usize {
+        chars
+            .get(idx.saturating_add(len))
+            .map_or(text.len(), |&(byte_idx, _)| byte_idx)
+    }
+
+    /// Consumes comment text and returns how many characters belong to the
+    /// comment on this line. Rust block comments nest; C ones do not.
+    fn block_comment_len(&mut self, rest: &[(usize, char)]) -> usize {
+        let nested = self.file_type == FileType::Rust;
+        let mut idx: usize = 0;
+        while let Some(&(_, character)) = rest.get(idx) {
+            let next = rest.get(idx.saturating_add(1)).map(|&(_, next)| next);
+            if character == '/' && next == Some('*') && (nested || self.block_comment_depth == 0) {
+                self.block_comment_depth = self.block_comment_depth.saturating_add(1);
+                idx = idx.saturating_add(2);
+            } else if character == '*' && next == Some('/') && self.block_comment_depth > 0 {
+                self.block_comment_depth = self.block_comment_depth.saturating_sub(1);
+                idx = idx.saturating_add(2);
+                if self.block_comment_depth == 0 {
+                    return idx;
+                }
+            } else {
+                idx = idx.saturating_add(1);
+            }
+        }
+        rest.len()
+    }
+
+    fn string_len(rest: &[(usize, char)]) -> usize {
+        let mut idx: usize = 1;
+        while let Some(&(_, character)) = rest.get(idx) {
+            match character {
+                '\\' => idx = idx.saturating_add(2),
+                '"' => return idx.saturating_add(1),
+                _ => idx = idx.saturating_add(1),
+            }
+        }
+        rest.len()
+    }
+
+    /// Returns the length of a char literal such as `'a'` or `'\n'`, or `None`
+    /// for a lifetime like `'a`.
+    fn char_literal_len(rest: &[(usize, char)]) -> Option<usize> {
+        match rest.get(1).map(|&(_, character)| character) {
+            // The escaped character itself may be a quote, as in `'\''`.
+            Some('\\') => rest
+                .iter()
+                .skip(3)
+                .position(|&(_, character)| character == '\'')
+                .map(|position| position.saturating_add(4)),
+            Some(_) => rest
+                .get(2)
+                .is_some_and(|&(_, character)| character == '\'')
+                .then_some(3),
+            None => None,
+        }
+    }
+
+    fn number_len(rest: &[(usize, char)]) -> usize {
+        let mut idx: usize = 0;
+        while let Some(&(_, character)) = rest.get(idx) {
+            let next_is_digit = rest
+                .get(idx.saturating_add(1))
+                .is_some_and(|&(_, next)| next.is_ascii_digit());
+            if character.is_alphanumeric()
+                || character == '_'
+                || (character == '.' && next_is_digit)
+            {
+                idx = idx.saturating_add(1);
+            } else {
+                break;
+            }
+        }
+        idx
+    }
+
+    fn word_len(rest: &[(usize, char)]) -> usize {
+        rest.iter()
+            .take_while(|&&(_, character)| character.is_alphanumeric() || character == '_')
+            .count()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::super::annotatedstring::AnnotationType;
+    use super::super::super::filetype::FileType;
+    use super::super::line::Line;
+    use super::Highlighter;
+
+    fn kinds(text: &str) -> Vec<(AnnotationType, usize, usize)> {
+        Highlighter::for_file_type(FileType::Rust)
+            .highlight_line(&Line::from(text))
+            .into_iter()
+            .map(|annotation| (annotation.kind, annotation.start, annotation.end))
+            .collect()
+    }
+
+    #[test]
+    fn escaped_quotes_and_backslashes_end_char_literals() {
+        let char_at_start = [(AnnotationType::Character, 0, 4)];
+        assert!(kinds(r"'\'' x") == char_at_start);
+        assert!(kinds(r"'\\' x") == char_at_start);
+        assert!(kinds(r"'\n' x") == char_at_start);
+        assert!(kinds(r"'\u{41}'") == [(AnnotationType::Character, 0, 8)]);
+    }
+}
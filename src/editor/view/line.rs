@@ -0,0 +1,339 @@
+use super::super::annotatedstring::{AnnotatedString, Annotation, AnnotationType};
+use super::query::Query;
+use core::cmp::min;
+use core::fmt::{self, Display};
+use core::ops::Range;
+use regex::Regex;
+use unicode_segmentation::UnicodeSegmentation;
+use unicode_width::UnicodeWidthStr;
+
+#[derive(Clone, Copy)]
+enum GraphemeWidth {
+    Half,
+    Full,
+    Tab,
+}
+
+impl GraphemeWidth {
+    /// The columns taken when drawn at `column`; a tab stretches to the next
+    /// multiple of `tab_width`.
+    fn columns_at(self, column: usize, tab_width: usize) -> usize {
+        match self {
+            Self::Half => 1,
+            Self::Full => 2,
+            Self::Tab => {
+                let tab_width = tab_width.max(1);
+                tab_width.saturating_sub(column.checked_rem(tab_width).unwrap_or(0))
+            }
+        }
+    }
+}
+
+#[derive(Clone)]
+struct TextFragment {
+    grapheme: String,
+    rendered_width: GraphemeWidth,
+    replacement: Option<char>,
+    start_byte_idx: usize,
+}
+
+#[derive(Default, Clone)]
+pub struct Line {
+    fragments: Vec<TextFragment>,
+    string: String,
+}
+
+impl Line {
+    pub fn from(line_str: &str) -> Self {
+        Self {
+            fragments: Self::str_to_fragments(line_str),
+            string: String::from(line_str),
+        }
+    }
+
+    fn str_to_fragments(line_str: &str) -> Vec<TextFragment> {
+        line_str
+            .grapheme_indices(true)
+            .map(|(start_byte_idx, grapheme)| {
+                let replacement = Self::replacement_character(grapheme);
+                let rendered_width = if grapheme == "\t" {
+                    GraphemeWidth::Tab
+                } else if replacement.is_some() {
+                    GraphemeWidth::Half
+                } else {
+                    match grapheme.width() {
+                        0 | 1 => GraphemeWidth::Half,
+                        _ => GraphemeWidth::Full,
+                    }
+                };
+                TextFragment {
+                    grapheme: grapheme.to_string(),
+                    rendered_width,
+                    replacement,
+                    start_byte_idx,
+                }
+            })
+            .collect()
+    }
+
+    fn replacement_character(for_str: &str) -> Option<char> {
+        let width = for_str.width();
+        match for_str {
+            " " => None,
+            "\t" => Some(' '),
+            _ if width > 0 && for_str.trim().is_empty() => Some('␣'),
+            _ if width == 0 => {
+                let mut chars = for_str.chars();
+                if let Some(ch) = chars.next()
+                    && ch.is_control()
+                    && chars.next().is_none()
+                {
+                    return Some('▯');
+                }
+                Some('·')
+            }
+            _ => None,
+        }
+    }
+
+    pub fn annotated_visible_substr(
+        &self,
+        range: Range<usize>,
+        annotations: &[Annotation],
+        tab_width: usize,
+    ) -> AnnotatedString {
+        let mut result = AnnotatedString::default();
+        if range.start >= range.end {
+            return result;
+        }
+        let mut current_pos: usize = 0;
+        for (grapheme_idx, fragment) in self.fragments.iter().enumerate() {
+            let fragment_end = current_pos
+                .saturating_add(fragment.rendered_width.columns_at(current_pos, tab_width));
+            if current_pos >= range.end {
+                break;
+            }
+            if fragment_end > range.start {
+                // Later annotations take precedence over earlier ones, except
+                // that misspellings only add an underline.
+                let kind = annotations
+                    .iter()
+                    .rev()
+                    .find(|annotation| {
+                        annotation.kind != AnnotationType::Misspelled
+                            && annotation.contains(grapheme_idx)
+                    })
+                    .map(|annotation| annotation.kind);
+                let underlined = annotations.iter().any(|annotation| {
+                    annotation.kind == AnnotationType::Misspelled
+                        && annotation.contains(grapheme_idx)
+                });
+                if let GraphemeWidth::Tab = fragment.rendered_width {
+                    let visible =
+                        min(fragment_end, range.end).saturating_sub(current_pos.max(range.start));
+                    result.push_underlined(&" ".repeat(visible), kind, underlined);
+                } else if fragment_end > range.end || current_pos < range.start {
+                    // The fragment is only partially visible, so hint at it instead.
+                    result.push_underlined("⋯", kind, underlined);
+                } else if let Some(replacement) = fragment.replacement {
+                    result.push_underlined(&replacement.to_string(), kind, underlined);
+                } else {
+                    result.push_underlined(&fragment.grapheme, kind, underlined);
+                }
+            }
+            current_pos = fragment_end;
+        }
+        result
+    }
+
+    pub fn grapheme_count(&self) -> usize {
+        self.fragments.len()
+    }
+
+    pub fn width_until(&self, grapheme_index: usize, tab_width: usize) -> usize {
+        self.fragments
+            .iter()
+            .take(grapheme_index)
+            .fold(0, |column, fragment| {
+                column.saturating_add(fragment.rendered_width.columns_at(column, tab_width))
+            })
+    }
+
+    /// The index of the grapheme drawn at `column`, or the grapheme count if
+    /// the line ends before it.
+    pub fn grapheme_idx_at_column(&self, column: usize, tab_width: usize) -> usize {
+        let mut start: usize = 0;
+        for (grapheme_idx, fragment) in self.fragments.iter().enumerate() {
+            let end = start.saturating_add(fragment.rendered_width.columns_at(start, tab_width));
+            if column < end {
+                return grapheme_idx;
+            }
+            start = end;
+        }
+        self.fragments.len()
+    }
+
+    pub fn as_str(&self) -> &str {
+        &self.string
+    }
+
+    /// Returns the grapheme index each row starts at when the line is wrapped
+    /// to `width` columns, breaking after whitespace where possible.
+    pub fn wrap_points(&self, width: usize, tab_width: usize) -> Vec<usize> {
+        let mut starts = vec![0];
+        if width == 0 {
+            return starts;
+        }
+        let mut columns = Vec::with_capacity(self.fragments.len().saturating_add(1));
+        let mut column: usize = 0;
+        columns.push(column);
+        for fragment in &self.fragments {
+            column = column.saturating_add(fragment.rendered_width.columns_at(column, tab_width));
+            columns.push(column);
+        }
+        let column_at = |grapheme_idx: usize| columns.get(grapheme_idx).copied().unwrap_or(0);
+        let mut row_start: usize = 0;
+        let mut last_break: Option<usize> = None;
+        for (grapheme_idx, fragment) in self.fragments.iter().enumerate() {
+            let fragment_end = grapheme_idx.saturating_add(1);
+            while grapheme_idx > row_start
+                && column_at(fragment_end).saturating_sub(column_at(row_start)) > width
+            {
+                let break_at = last_break
+                    .filter(|&break_at| break_at > row_start)
+                    .unwrap_or(grapheme_idx);
+                starts.push(break_at);
+                row_start = break_at;
+                last_break = None;
+            }
+            if fragment.grapheme.trim().is_empty() {
+                last_break = Some(fragment_end);
+            }
+        }
+        starts
+    }
+
+    /// Grapheme indices at which an alphanumeric word begins.
+    pub fn word_starts(&self) -> Vec<usize> {
+        self.string
+            .split_word_bound_indices()
+            .filter(|(_, word)| word.chars().next().is_some_and(char::is_alphanumeric))
+            .filter_map(|(byte_idx, _)| self.byte_idx_to_grapheme_idx(byte_idx))
+            .collect()
+    }
+
+    /// The alphanumeric words of the line with their grapheme ranges.
+    pub fn words(&self) -> impl Iterator<Item = (Range<usize>, &str)> {
+        self.string
+            .split_word_bound_indices()
+            .filter(|(_, word)| word.chars().next().is_some_and(char::is_alphanumeric))
+            .filter_map(|(byte_idx, word)| {
+                let start = self.byte_idx_to_grapheme_idx(byte_idx)?;
+                let end = self.byte_idx_to_grapheme_idx(byte_idx.saturating_add(word.len()))?;
+                Some((start..end, word))
+            })
+    }
+
+    /// How many alphanumeric words the line holds, counted the way
+    /// `word_starts` finds them.
+    pub fn word_count(&self) -> usize {
+        self.string
+            .split_word_bounds()
+            .filter(|word| word.chars().next().is_some_and(char::is_alphanumeric))
+            .count()
+    }
+
+    /// The grapheme range of the word under or just before `grapheme_idx`.
+    pub fn word_at(&self, grapheme_idx: usize) -> Option<Range<usize>> {
+        let byte_idx = self.grapheme_idx_to_byte_idx(grapheme_idx);
+        let (start, word) = self
+            .string
+            .split_word_bound_indices()
+            .filter(|(_, word)| word.chars().next().is_some_and(char::is_alphanumeric))
+            .find(|&(start, word)| {
+                start <= byte_idx && byte_idx <= start.saturating_add(word.len())
+            })?;
+        Some(
+            self.byte_idx_to_grapheme_idx(start)?
+                ..self
+                    .byte_idx_to_grapheme_idx(start.saturating_add(word.len()))
+                    .unwrap_or_else(|| self.grapheme_count()),
+        )
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.fragments.is_empty()
+    }
+
+    pub fn grapheme_idx_to_byte_idx(&self, grapheme_idx: usize) -> usize {
+        self.fragments
+            .get(grapheme_idx)
+            .map_or(self.string.len(), |fragment| fragment.start_byte_idx)
+    }
+
+    pub fn byte_idx_to_grapheme_idx(&self, byte_idx: usize) -> Option<usize> {
+        if byte_idx == self.string.len() {
+            return Some(self.fragments.len());
+        }
+        self.fragments
+            .binary_search_by_key(&byte_idx, |fragment| fragment.start_byte_idx)
+            .ok()
+    }
+
+    /// Returns the grapheme ranges of all matches of `query`, skipping any
+    /// match that would start or end in the middle of a grapheme.
+    pub fn find_all(&self, query: &Query) -> Vec<Range<usize>> {
+        query
+            .find_in(&self.string)
+            .into_iter()
+            .filter_map(|range| {
+                let start = self.byte_idx_to_grapheme_idx(range.start)?;
+                let end = self.byte_idx_to_grapheme_idx(range.end)?;
+                Some(start..end)
+            })
+            .collect()
+    }
+
+    /// The grapheme ranges of the capture groups of each nonempty match of
+    /// `re`, the whole match first. Matches that would start or end in the
+    /// middle of a grapheme are skipped, and so are such groups.
+    pub fn captures(&self, re: &Regex) -> Vec<Vec<Option<Range<usize>>>> {
+        re.captures_iter(&self.string)
+            .filter(|captures| captures.get(0).is_some_and(|whole| !whole.is_empty()))
+            .map(|captures| {
+                captures
+                    .iter()
+                    .map(|group| {
+                        let group = group?;
+                        Some(
+                            self.byte_idx_to_grapheme_idx(group.start())?
+                                ..self.byte_idx_to_grapheme_idx(group.end())?,
+                        )
+                    })
+                    .collect::<Vec<_>>()
+            })
+            .filter(|groups| groups.first().is_some_and(Option::is_some))
+            .collect()
+    }
+
+    pub fn search_forward(&self, query: &Query, from_grapheme_idx: usize) -> Option<usize> {
+        self.find_all(query)
+            .into_iter()
+            .map(|range| range.start)
+            .find(|&grapheme_idx| grapheme_idx >= from_grapheme_idx)
+    }
+
+    pub fn search_backward(&self, query: &Query, before_grapheme_idx: usize) -> Option<usize> {
+        self.find_all(query)
+            .into_iter()
+            .rev()
+            .map(|range| range.start)
+            .find(|&grapheme_idx| grapheme_idx < before_grapheme_idx)
+    }
+}
+
+impl Display for Line {
+    fn fmt(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        write!(formatter, "{}", self.string)
+    }
+}
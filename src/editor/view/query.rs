@@ -0,0 +1,137 @@
+use core::ops::Range;
+use regex::{Regex, RegexBuilder};
+use unicode_segmentation::UnicodeSegmentation;
+
+/// What to search for. Typing `\c` in front of the text ignores case, `\w`
+/// only matches whole words, with words split as word movement does, and
+/// `\r` reads the text as a regular expression.
+#[derive(Clone, Default)]
+pub struct Query {
+    pub text: String,
+    pub ignore_case: bool,
+    pub whole_word: bool,
+    pub is_regex: bool,
+    regex: Option<Regex>,
+}
+
+impl Query {
+    /// Fails with a message if the text should be a regular expression but
+    /// isn't a valid one.
+    pub fn parse(input: &str) -> Result<Self, String> {
+        let mut query = Self::default();
+        let mut rest = input;
+        loop {
+            if let Some(after) = rest.strip_prefix("\\c") {
+                query.ignore_case = true;
+                rest = after;
+            } else if let Some(after) = rest.strip_prefix("\\w") {
+                query.whole_word = true;
+                rest = after;
+            } else if let Some(after) = rest.strip_prefix("\\r") {
+                query.is_regex = true;
+                rest = after;
+            } else {
+                break;
+            }
+        }
+        query.text = rest.to_string();
+        if query.is_regex && !rest.is_empty() {
+            let regex = RegexBuilder::new(rest)
+                .case_insensitive(query.ignore_case)
+                .build()
+                .map_err(|err| {
+                    // The last line names the problem; the ones before it
+                    // point at where it is.
+                    let message = err.to_string();
+                    let problem = message.lines().last().unwrap_or_default();
+                    format!("Invalid regex: {}", problem.trim_start_matches("error: "))
+                })?;
+            query.regex = Some(regex);
+        }
+        Ok(query)
+    }
+
+    pub const fn regex(&self) -> Option<&Regex> {
+        self.regex.as_ref()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.text.is_empty()
+    }
+
+    /// The toggles in effect, as shown in the search prompt.
+    pub fn flags(&self) -> String {
+        let flags: Vec<&str> = [
+            (self.ignore_case, "ignore case"),
+            (self.whole_word, "whole word"),
+            (self.is_regex, "regex"),
+        ]
+        .into_iter()
+        .filter_map(|(enabled, flag)| enabled.then_some(flag))
+        .collect();
+        if flags.is_empty() {
+            String::new()
+        } else {
+            format!(" [{}]", flags.join(", "))
+        }
+    }
+
+    /// The byte ranges of the matches in `text`, which never overlap.
+    pub fn find_in(&self, text: &str) -> Vec<Range<usize>> {
+        if self.is_empty() {
+            return Vec::new();
+        }
+        let matches: Vec<Range<usize>> = if let Some(regex) = &self.regex {
+            regex
+                .find_iter(text)
+                .filter(|found| !found.is_empty())
+                .map(|found| found.range())
+                .collect()
+        } else if self.ignore_case {
+            let mut matches = Vec::new();
+            let mut next_start = 0;
+            for (start, _) in text.char_indices() {
+                if start < next_start {
+                    continue;
+                }
+                if let Some(len) = self.match_len_ignoring_case(&text[start..]) {
+                    next_start = start.saturating_add(len);
+                    matches.push(start..next_start);
+                }
+            }
+            matches
+        } else {
+            text.match_indices(self.text.as_str())
+                .map(|(start, matched)| start..start.saturating_add(matched.len()))
+                .collect()
+        };
+        if !self.whole_word {
+            return matches;
+        }
+        let bounds: Vec<usize> = text
+            .split_word_bound_indices()
+            .map(|(byte_idx, _)| byte_idx)
+            .chain(core::iter::once(text.len()))
+            .collect();
+        matches
+            .into_iter()
+            .filter(|range| {
+                bounds.binary_search(&range.start).is_ok()
+                    && bounds.binary_search(&range.end).is_ok()
+            })
+            .collect()
+    }
+
+    /// How many bytes at the start of `text` match the query when case is
+    /// ignored, comparing one character at a time.
+    fn match_len_ignoring_case(&self, text: &str) -> Option<usize> {
+        let mut haystack = text.char_indices();
+        for expected in self.text.chars() {
+            let (_, found) = haystack.next()?;
+            if found != expected && !found.to_lowercase().eq(expected.to_lowercase()) {
+                return None;
+            }
+        }
+        Some(haystack.next().map_or(text.len(), |(byte_idx, _)| byte_idx))
+    }
+}
@@ -0,0 +1,349 @@
+use super::super::Location;
+use std::collections::BTreeMap;
+
+const MAX_UNDO_STEPS: usize = 1000;
+
+/// A primitive change to the buffer. Newlines are part of `text`, so
+/// splitting a line is an insert of `"\n"` and joining two lines is a delete
+/// of one.
+#[derive(Clone)]
+pub enum Edit {
+    Insert {
+        at: Location,
+        end: Location,
+        text: String,
+    },
+    Delete {
+        at: Location,
+        end: Location,
+        text: String,
+    },
+}
+
+impl Edit {
+    pub const fn start(&self) -> Location {
+        match self {
+            Self::Insert { at, .. } | Self::Delete { at, .. } => *at,
+        }
+    }
+
+    pub fn text(&self) -> &str {
+        match self {
+            Self::Insert { text, .. } | Self::Delete { text, .. } => text,
+        }
+    }
+
+    /// Where the caret belongs once this edit has been applied.
+    pub const fn caret_after(&self) -> Location {
+        match self {
+            Self::Insert { end, .. } => *end,
+            Self::Delete { at, .. } => *at,
+        }
+    }
+}
+
+/// A state of the buffer in the undo tree, reached from its parent by
+/// applying `edits`.
+struct Node {
+    edits: Vec<Edit>,
+    parent: Option<usize>,
+    children: Vec<usize>,
+    /// The child that redo goes back to: the one last undone, or else the
+    /// newest.
+    redo_child: Option<usize>,
+}
+
+/// A point in the undo tree where edits went more than one way.
+pub struct BranchPoint {
+    pub change: usize,
+    pub branches: usize,
+}
+
+/// Where the buffer is in its undo history, for `:undolist`.
+pub struct UndoList {
+    pub current: usize,
+    pub newest: usize,
+    pub branch_points: Vec<BranchPoint>,
+}
+
+/// The changes that take the buffer from one state in the undo tree to
+/// another: steps to revert, in order, followed by steps to apply.
+pub struct Path {
+    pub revert: Vec<Vec<Edit>>,
+    pub apply: Vec<Vec<Edit>>,
+}
+
+/// Undo history as a tree, so that editing after an undo starts a new
+/// branch instead of throwing the undone changes away. Nodes are keyed by
+/// change number, which only ever grows, so the keys also give the order
+/// the changes were made in. The root, change 0 until pruning moves it, is
+/// the state the history starts from.
+pub struct UndoStack {
+    nodes: BTreeMap<usize, Node>,
+    root: usize,
+    current: usize,
+    next_change: usize,
+}
+
+impl Default for UndoStack {
+    fn default() -> Self {
+        let root = Node {
+            edits: Vec::new(),
+            parent: None,
+            children: Vec::new(),
+            redo_child: None,
+        };
+        Self {
+            nodes: BTreeMap::from([(0, root)]),
+            root: 0,
+            current: 0,
+            next_change: 1,
+        }
+    }
+}
+
+impl UndoStack {
+    /// Records `edits` as a single undo step after the current state.
+    pub fn record(&mut self, edits: Vec<Edit>) {
+        if edits.is_empty() {
+            return;
+        }
+        let change = self.next_change;
+        self.next_change = self.next_change.saturating_add(1);
+        self.nodes.insert(
+            change,
+            Node {
+                edits,
+                parent: Some(self.current),
+                children: Vec::new(),
+                redo_child: None,
+            },
+        );
+        if let Some(parent) = self.nodes.get_mut(&self.current) {
+            parent.children.push(change);
+            parent.redo_child = Some(change);
+        }
+        self.current = change;
+        self.prune();
+    }
+
+    /// Records a typed character, merging it into the previous step while
+    /// the user keeps typing a word on the same line.
+    pub fn record_typed(&mut self, edit: Edit) {
+        if let Edit::Insert { at, text, .. } = &edit
+            && !text.chars().all(char::is_whitespace)
+            && self.current != self.root
+            && let Some(node) = self.nodes.get_mut(&self.current)
+            && node.children.is_empty()
+            && let Some(Edit::Insert {
+                end: previous_end,
+                text: previous_text,
+                ..
+            }) = node.edits.last()
+            && previous_end == at
+            && previous_text.chars().count() == 1
+            && previous_text != "\n"
+        {
+            node.edits.push(edit);
+            return;
+        }
+        self.record(vec![edit]);
+    }
+
+    /// The current state, for `merge_since`.
+    pub const fn current(&self) -> usize {
+        self.current
+    }
+
+    /// Folds the steps recorded since the state `since` into a single step.
+    pub fn merge_since(&mut self, since: usize) {
+        let mut chain = Vec::new();
+        let mut change = self.current;
+        while change != since {
+            let Some(parent) = self.nodes.get(&change).and_then(|node| node.parent) else {
+                return;
+            };
+            chain.push(change);
+            change = parent;
+        }
+        let Some((&first, rest)) = chain.split_last() else {
+            return;
+        };
+        let mut merged = Vec::new();
+        for change in rest.iter().rev() {
+            if let Some(node) = self.nodes.remove(change) {
+                merged.extend(node.edits);
+            }
+        }
+        if let Some(node) = self.nodes.get_mut(&first) {
+            node.edits.extend(merged);
+            node.children.clear();
+            node.redo_child = None;
+        }
+        self.current = first;
+    }
+
+    /// Steps back to the parent state, returning the edits to revert.
+    pub fn undo(&mut self) -> Option<Vec<Edit>> {
+        let node = self.nodes.get(&self.current)?;
+        let parent = node.parent?;
+        let edits = node.edits.clone();
+        if let Some(parent_node) = self.nodes.get_mut(&parent) {
+            parent_node.redo_child = Some(self.current);
+        }
+        self.current = parent;
+        Some(edits)
+    }
+
+    /// Steps forward along the branch last undone, returning the edits to
+    /// apply.
+    pub fn redo(&mut self) -> Option<Vec<Edit>> {
+        let child = self.nodes.get(&self.current)?.redo_child?;
+        let edits = self.nodes.get(&child)?.edits.clone();
+        self.current = child;
+        Some(edits)
+    }
+
+    /// Moves to the state recorded just before the current one, or just
+    /// after it with `later`, whichever branch that is on.
+    pub fn travel(&mut self, later: bool) -> Option<Path> {
+        let target = if later {
+            self.nodes.range(self.current.saturating_add(1)..).next()
+        } else {
+            self.nodes.range(..self.current).next_back()
+        }
+        .map(|(&change, _)| change)?;
+        let mut from_current = self.ancestors(self.current);
+        let mut from_target = self.ancestors(target);
+        let common = *from_current
+            .iter()
+            .find(|change| from_target.contains(change))?;
+        from_current.truncate(from_current.iter().position(|&change| change == common)?);
+        from_target.truncate(from_target.iter().position(|&change| change == common)?);
+        let mut path = Path {
+            revert: Vec::new(),
+            apply: Vec::new(),
+        };
+        for change in &from_current {
+            path.revert.push(self.nodes.get(change)?.edits.clone());
+        }
+        for &change in from_target.iter().rev() {
+            path.apply.push(self.nodes.get(&change)?.edits.clone());
+            let parent = self.nodes.get(&change)?.parent?;
+            if let Some(parent_node) = self.nodes.get_mut(&parent) {
+                parent_node.redo_child = Some(change);
+            }
+        }
+        self.current = target;
+        Some(path)
+    }
+
+    /// `change` and the states above it, up to the root.
+    fn ancestors(&self, change: usize) -> Vec<usize> {
+        let mut ancestors = vec![change];
+        let mut node = self.nodes.get(&change);
+        while let Some(parent) = node.and_then(|node| node.parent) {
+            ancestors.push(parent);
+            node = self.nodes.get(&parent);
+        }
+        ancestors
+    }
+
+    pub fn list(&self) -> UndoList {
+        UndoList {
+            current: self.current,
+            newest: self.nodes.keys().next_back().copied().unwrap_or(0),
+            branch_points: self
+                .nodes
+                .iter()
+                .filter(|(_, node)| node.children.len() > 1)
+                .map(|(&change, node)| BranchPoint {
+                    change,
+                    branches: node.children.len(),
+                })
+                .collect(),
+        }
+    }
+
+    /// Keeps the tree to `MAX_UNDO_STEPS` changes by letting go of the oldest
+    /// ones: the root moves down towards the current state, dropping the
+    /// branches it leaves behind. Only `record` prunes, and it always leaves
+    /// the current state below the root.
+    fn prune(&mut self) {
+        while self.nodes.len() > MAX_UNDO_STEPS.saturating_add(1) {
+            let Some(&keep) = self.ancestors(self.current).iter().rev().nth(1) else {
+                return;
+            };
+            let old_root = self.root;
+            let children = self
+                .nodes
+                .remove(&old_root)
+                .map(|node| node.children)
+                .unwrap_or_default();
+            for child in children.into_iter().filter(|&child| child != keep) {
+                self.remove_subtree(child);
+            }
+            if let Some(node) = self.nodes.get_mut(&keep) {
+                node.edits.clear();
+                node.parent = None;
+            }
+            self.root = keep;
+        }
+    }
+
+    fn remove_subtree(&mut self, change: usize) {
+        let mut pending = vec![change];
+        while let Some(change) = pending.pop() {
+            if let Some(node) = self.nodes.remove(&change) {
+                pending.extend(node.children);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::super::Location;
+    use super::{Edit, MAX_UNDO_STEPS, UndoStack};
+
+    fn record_one(stack: &mut UndoStack) {
+        stack.record(vec![Edit::Insert {
+            at: Location::default(),
+            end: Location { x: 1, y: 0 },
+            text: String::from("x"),
+        }]);
+    }
+
+    #[test]
+    fn pruning_drops_the_branches_the_root_moves_past() {
+        let mut stack = UndoStack::default();
+        for _ in 0..3 {
+            record_one(&mut stack);
+        }
+        stack.undo();
+        stack.undo();
+        // Change 4 branches off change 1, leaving 2 and 3 on the other branch.
+        record_one(&mut stack);
+        assert_eq!(stack.list().branch_points.len(), 1);
+        while stack.root == 0 {
+            record_one(&mut stack);
+        }
+        // Moving onto change 1 keeps both of its branches.
+        assert_eq!(stack.root, 1);
+        assert!(stack.nodes.contains_key(&2) && stack.nodes.contains_key(&3));
+        assert_eq!(stack.nodes.len(), MAX_UNDO_STEPS.saturating_add(1));
+        while stack.root == 1 {
+            record_one(&mut stack);
+        }
+        // Moving past it drops the branch the current state isn't on.
+        assert_eq!(stack.root, 4);
+        assert!(!stack.nodes.contains_key(&2) && !stack.nodes.contains_key(&3));
+        assert!(stack.list().branch_points.is_empty());
+        let mut undone: usize = 0;
+        while stack.undo().is_some() {
+            undone = undone.saturating_add(1);
+        }
+        assert_eq!(undone, stack.nodes.len().saturating_sub(1));
+        assert_eq!(stack.current, 4);
+    }
+}